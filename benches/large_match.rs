@@ -0,0 +1,80 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Times matches of the size of a school district
+//!
+//! Run with `cargo bench`. Every match is run a few times and the fastest run is reported.
+
+use matchmaker::{Category, Matcher, Mechanism, Student};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of preferences of every student
+const PREFERENCES: usize = 5;
+/// Number of times every match is run
+const RUNS: usize = 5;
+
+/// Create students that each list a few random categories, where some students have
+/// priority in every category, like siblings of current students
+fn get_data(student_count: usize, category_count: usize) -> (Vec<Student>, Vec<Category>) {
+    let mut rng = StdRng::seed_from_u64(42);
+    let names: Vec<String> = (0..student_count)
+        .map(|i| format!("Student {}", i))
+        .collect();
+    let capacity = student_count / category_count;
+
+    let mut categories: Vec<Category> = (0..category_count)
+        .map(|i| Category::new(&format!("Category {}", i), capacity))
+        .collect();
+    for category in categories.iter_mut() {
+        for name in names.choose_multiple(&mut rng, capacity / 10) {
+            category
+                .priorities
+                .insert(name.clone(), rng.gen_range(1, 3));
+        }
+    }
+
+    let students = names
+        .iter()
+        .map(|name| {
+            let preferences: VecDeque<Category> = categories
+                .choose_multiple(&mut rng, PREFERENCES)
+                .cloned()
+                .collect();
+            Student::new(name, preferences, Vec::new())
+        })
+        .collect();
+
+    (students, categories)
+}
+
+fn main() {
+    for &(student_count, category_count) in &[(4_000, 30), (40_000, 300)] {
+        let (students, categories) = get_data(student_count, category_count);
+
+        for &mechanism in &[Mechanism::DaStb, Mechanism::DaMtb, Mechanism::Ttc] {
+            let mut rng = StdRng::seed_from_u64(1);
+            let fastest = (0..RUNS)
+                .map(|_| {
+                    let start = Instant::now();
+                    mechanism
+                        .match_students(students.clone(), &categories, &mut rng)
+                        .unwrap();
+                    start.elapsed()
+                })
+                .min()
+                .unwrap_or(Duration::ZERO);
+
+            println!(
+                "{:?} with {} students and {} categories: {:?}",
+                mechanism, student_count, category_count, fastest
+            );
+        }
+    }
+}
@@ -5,11 +5,11 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use matchmaker::da_stb::match_students;
-use matchmaker::{Category, Student};
+use matchmaker::{Category, MatchError, Student};
 use rand::thread_rng;
 use std::collections::VecDeque;
 
-fn main() {
+fn main() -> Result<(), MatchError> {
     // Create categories
     let cooking = Category::new("Cooking", 10);
     let reading = Category::new("Reading", 10);
@@ -35,18 +35,18 @@ fn main() {
     let mut rng = thread_rng();
     let categories = Vec::from([cooking, reading, walking]);
 
-    let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng);
+    let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng)?;
 
     println!("Students matched to categories:");
     println!();
     for category in &categories {
         println!("{}:", &category.name);
-        for student in match_result
+        for name in match_result
             .placed
             .get(&category.name)
             .unwrap_or(&Vec::new())
         {
-            println!(" - {}", &student.name);
+            println!(" - {}", name);
         }
     }
 
@@ -54,4 +54,6 @@ fn main() {
         println!();
         println!("All students could be placed.");
     }
+
+    Ok(())
 }
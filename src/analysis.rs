@@ -0,0 +1,578 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Analyses the result of a match
+
+use super::da_stb::{select_students, sort_by_priority};
+use super::{
+    validate, Category, Lottery, MatchError, MatchResult, Matcher, OrderedStudent, Priorities,
+    Student, Violation,
+};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+/// Holds a student and a category that would both rather be matched to each other
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlockingPair {
+    /// Name of the student that prefers the category to their placement
+    pub student: String,
+    /// Name of the category
+    pub category: String,
+    /// Name of the placed student with the lowest priority or worst lot that the student could
+    /// replace, or `None` when the category has open spots
+    pub displaced: Option<String>,
+}
+
+/// Find every blocking pair in the result of a match
+///
+/// A student and a category form a blocking pair when the student prefers the category to
+/// their placement (categories in the same indifference group are equally preferred), and
+/// the category either has open spots or placed a student with a lower priority or a worse
+/// lot. A match without blocking pairs is stable. Cancelled categories can't block, and
+/// students with a fixed placement neither block nor get displaced. A full category with
+/// reserved seats only blocks when it would select the student over a placed student,
+/// filling its reserved seats the way the match does, so a student can't displace a student
+/// on a seat reserved for an attribute the student doesn't have.
+///
+/// Use this function with the result of a match where each student can only be placed in one
+/// category, including results that were edited afterwards. Lots are taken from
+/// `match_result.category_lottery_numbers` when the mechanism drew a lottery per category, like
+/// [`da_mtb`](../da_mtb/index.html), and from `match_result.lottery_numbers` otherwise.
+///
+/// # Arguments
+///
+/// * `students` - Students of the match, with their original preferences
+/// * `categories` - Categories of the match
+/// * `match_result` - Result of the match
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], when a student has no lottery number in the result, or when a student of
+/// the result is not part of the match.
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::analysis::find_blocking_pairs;
+/// use matchmaker::da_stb::match_students;
+/// use matchmaker::{Category, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let reading = Category::new("Reading", 1);
+///
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// let mut rng = thread_rng();
+/// let students = Vec::from([bert, suze]);
+/// let categories = Vec::from([cooking, reading]);
+///
+/// let mut match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+///
+/// // Deferred acceptance gives a stable result
+/// let blocking_pairs = find_blocking_pairs(&students, &categories, &match_result).unwrap();
+/// assert!(blocking_pairs.is_empty());
+///
+/// // Swapping the students by hand breaks stability
+/// let cooking_students = match_result.placed.remove("Cooking").unwrap();
+/// let reading_students = match_result.placed.remove("Reading").unwrap();
+/// match_result.placed.insert("Cooking".into(), reading_students);
+/// match_result.placed.insert("Reading".into(), cooking_students);
+///
+/// let blocking_pairs = find_blocking_pairs(&students, &categories, &match_result).unwrap();
+/// assert_eq!(blocking_pairs.len(), 1);
+/// ```
+pub fn find_blocking_pairs(
+    students: &[Student],
+    categories: &[Category],
+    match_result: &MatchResult,
+) -> Result<Vec<BlockingPair>, MatchError> {
+    validate(students, categories)?;
+
+    let ids: HashMap<&str, usize> = students
+        .iter()
+        .enumerate()
+        .map(|(id, s)| (s.name.as_str(), id))
+        .collect();
+
+    let mut violations: Vec<Violation> = students
+        .iter()
+        .filter(|s| !match_result.lottery_numbers.contains_key(&s.name))
+        .map(|s| Violation::UnknownStudent(s.name.clone()))
+        .collect();
+    violations.extend(
+        match_result
+            .placed
+            .values()
+            .flatten()
+            .filter(|name| !ids.contains_key(name.as_str()))
+            .map(|name| Violation::UnknownStudent(name.clone())),
+    );
+    if !violations.is_empty() {
+        return Err(MatchError::InvalidInput(violations));
+    }
+
+    let mut placements: HashMap<&str, &str> = HashMap::new();
+    for (category_name, placed_students) in match_result.placed.iter() {
+        for name in placed_students {
+            placements.insert(name, category_name);
+        }
+    }
+
+    let fixed = |name: &str| {
+        categories
+            .iter()
+            .any(|c| c.fixed_placements.iter().any(|f| f == name))
+    };
+
+    // Resolve the priorities once, for the categories with reserved seats.
+    let priorities = Priorities::new(students, categories);
+
+    let mut blocking_pairs: Vec<BlockingPair> = Vec::new();
+
+    for student in students.iter().filter(|s| !fixed(&s.name)) {
+        let placement_rank = placements
+            .get(student.name.as_str())
+            .and_then(|category_name| student.preference_rank(category_name));
+
+        // Only categories the student prefers to their placement can block.
+        for category in student.preferences.iter() {
+            if placement_rank.is_some() && student.preference_rank(&category.name) >= placement_rank
+            {
+                break;
+            }
+
+            // Take priorities and placements from the categories of the match.
+            let index = match categories.iter().position(|c| c.name == category.name) {
+                Some(index) if !match_result.cancelled.contains(&category.name) => index,
+                _ => continue,
+            };
+            let category = &categories[index];
+            let rank = |name: &str| {
+                (
+                    Reverse(category.priority(name)),
+                    lottery_number(match_result, name, &category.name),
+                )
+            };
+
+            let placed_students = match_result
+                .placed
+                .get(&category.name)
+                .map_or(&[][..], |p| &p[..]);
+
+            if placed_students.len() < category.max_placements {
+                blocking_pairs.push(BlockingPair {
+                    student: student.name.clone(),
+                    category: category.name.clone(),
+                    displaced: None,
+                });
+            } else if !category.reserves.is_empty() {
+                let selection = Selection {
+                    category,
+                    index,
+                    priorities: &priorities,
+                    students,
+                    ids: &ids,
+                };
+                if let Some(displaced) =
+                    displaced_by(student, &selection, placed_students, match_result, &fixed)
+                {
+                    blocking_pairs.push(BlockingPair {
+                        student: student.name.clone(),
+                        category: category.name.clone(),
+                        displaced: Some(displaced),
+                    });
+                }
+            } else if let Some(worst) = placed_students
+                .iter()
+                .filter(|name| !fixed(name))
+                .max_by_key(|name| rank(name))
+            {
+                if rank(&student.name) < rank(worst) {
+                    blocking_pairs.push(BlockingPair {
+                        student: student.name.clone(),
+                        category: category.name.clone(),
+                        displaced: Some(worst.clone()),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(blocking_pairs)
+}
+
+/// Return the lot of the student that decides its place in the category
+fn lottery_number(match_result: &MatchResult, student: &str, category: &str) -> Option<usize> {
+    if match_result.category_lottery_numbers.is_empty() {
+        match_result.lottery_numbers.get(student).copied()
+    } else {
+        match_result
+            .category_lottery_numbers
+            .get(student)
+            .and_then(|lots| lots.get(category))
+            .copied()
+    }
+}
+
+/// A category with reserved seats and what it takes to select its students like the match
+struct Selection<'a> {
+    category: &'a Category,
+    /// Index of the category in the match
+    index: usize,
+    priorities: &'a Priorities,
+    /// Students of the match
+    students: &'a [Student],
+    /// Id of every student of the match by name
+    ids: &'a HashMap<&'a str, usize>,
+}
+
+/// Return the name of the placed student that loses its seat in a full category with
+/// reserved seats when the student applies, or `None` when the student doesn't get a seat
+///
+/// Selects the students the way the match does, by priority and lot.
+fn displaced_by(
+    student: &Student,
+    selection: &Selection,
+    placed_students: &[String],
+    match_result: &MatchResult,
+    fixed: &dyn Fn(&str) -> bool,
+) -> Option<String> {
+    let no_indices = HashMap::new();
+    let ordered = |name: &String| {
+        let order = lottery_number(match_result, name, &selection.category.name)
+            .map_or(0, |number| number - 1);
+        let id = selection.ids[name.as_str()];
+        OrderedStudent {
+            group: None,
+            ..OrderedStudent::new(&selection.students[id], id, order, &no_indices)
+        }
+    };
+
+    let fixed_count = placed_students.iter().filter(|name| fixed(name)).count();
+    let mut candidates: Vec<OrderedStudent> = placed_students
+        .iter()
+        .filter(|name| !fixed(name))
+        .map(ordered)
+        .collect();
+    candidates.push(ordered(&student.name));
+    sort_by_priority(&mut candidates, selection.priorities, selection.index);
+
+    let seats = selection
+        .category
+        .max_placements
+        .saturating_sub(fixed_count);
+    let (accepted, rejected) = select_students(candidates, selection.category, seats);
+    if accepted.iter().any(|s| s.name == student.name) {
+        rejected.into_iter().last().map(|s| s.name)
+    } else {
+        None
+    }
+}
+
+/// Holds the estimated chances of every student, see [`simulate`](fn.simulate.html)
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Simulation {
+    /// Number of matches that were run
+    pub runs: usize,
+    /// Seed of the first lottery
+    pub seed: u64,
+    /// Share of the runs in which the student was placed in the category, per student name
+    /// and category name
+    pub placement_probabilities: BTreeMap<String, BTreeMap<String, f64>>,
+    /// Share of the runs in which the student ended up in `not_placable`, per student name
+    pub not_placable_probabilities: BTreeMap<String, f64>,
+}
+
+/// Estimate the chances of every student by running a match many times with different
+/// lotteries
+///
+/// Every run draws a new lottery from a single random number generator seeded by `seed`, so
+/// a simulation with the same input and seed gives the same estimates. The estimates get
+/// more precise with more runs.
+///
+/// # Arguments
+///
+/// * `students` - Students to match
+/// * `categories` - Categories to match the students to
+/// * `runs` - Number of matches to run
+/// * `seed` - Seed of the lotteries
+/// * `matcher` - Mechanism to simulate, like [`DaStb`] or a [`Mechanism`]
+///
+/// # Errors
+///
+/// Returns the error of the matcher, like [`MatchError::InvalidInput`] when the students and
+/// categories are inconsistent.
+///
+/// [`DaStb`]: ../da_stb/struct.DaStb.html
+/// [`Mechanism`]: ../enum.Mechanism.html
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::analysis::simulate;
+/// use matchmaker::da_stb::DaStb;
+/// use matchmaker::{Category, Student};
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let reading = Category::new("Reading", 1);
+///
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// let students = Vec::from([bert, suze]);
+/// let categories = Vec::from([cooking, reading]);
+///
+/// let simulation = simulate(&students, &categories, 1000, 42, &DaStb).unwrap();
+///
+/// // Bert gets the first choice about half of the time
+/// let odds = simulation.placement_probabilities["Bert"]["Cooking"];
+/// assert!(odds > 0.4 && odds < 0.6);
+/// assert_eq!(simulation.not_placable_probabilities["Bert"], 0.0);
+/// ```
+pub fn simulate<M>(
+    students: &[Student],
+    categories: &[Category],
+    runs: usize,
+    seed: u64,
+    matcher: &M,
+) -> Result<Simulation, MatchError>
+where
+    M: Matcher + ?Sized,
+{
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    let mut lottery = Lottery::from_bytes(bytes);
+
+    let mut placement_counts: HashMap<(String, String), usize> = HashMap::new();
+    let mut not_placable_counts: HashMap<String, usize> = HashMap::new();
+
+    for _ in 0..runs {
+        let match_result = matcher.match_students(students.to_vec(), categories, &mut lottery)?;
+
+        for (name, placements) in match_result.placements.into_iter() {
+            for placement in placements {
+                *placement_counts
+                    .entry((name.clone(), placement.category))
+                    .or_default() += 1;
+            }
+        }
+        for name in match_result.not_placable.into_iter() {
+            *not_placable_counts.entry(name).or_default() += 1;
+        }
+    }
+
+    let probability = |count: Option<&usize>| match runs {
+        0 => 0.0,
+        _ => count.copied().unwrap_or(0) as f64 / runs as f64,
+    };
+
+    Ok(Simulation {
+        runs,
+        seed,
+        placement_probabilities: students
+            .iter()
+            .map(|s| {
+                let probabilities = categories
+                    .iter()
+                    .map(|c| {
+                        let count = placement_counts.get(&(s.name.clone(), c.name.clone()));
+                        (c.name.clone(), probability(count))
+                    })
+                    .collect();
+                (s.name.clone(), probabilities)
+            })
+            .collect(),
+        not_placable_probabilities: students
+            .iter()
+            .map(|s| {
+                let count = not_placable_counts.get(&s.name);
+                (s.name.clone(), probability(count))
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn get_data() -> (Vec<Student>, Vec<Category>, MatchResult) {
+        let cooking = Category::new("Cooking", 1).with_priority("Kate", 1);
+        let reading = Category::new("Reading", 2);
+
+        let bert = Student::new(
+            "Bert",
+            VecDeque::from(vec![cooking.clone(), reading.clone()]),
+            Vec::new(),
+        );
+        let kate = Student::new(
+            "Kate",
+            VecDeque::from(vec![cooking.clone(), reading.clone()]),
+            Vec::new(),
+        );
+
+        let mut match_result = MatchResult::default();
+        match_result.lottery_numbers.insert(bert.name.clone(), 1);
+        match_result.lottery_numbers.insert(kate.name.clone(), 2);
+
+        (vec![bert, kate], vec![cooking, reading], match_result)
+    }
+
+    #[test]
+    fn test_find_blocking_pairs_stable() {
+        let (students, categories, mut match_result) = get_data();
+
+        // Kate has priority for cooking, so Bert can't take her spot.
+        match_result
+            .placed
+            .insert("Cooking".into(), vec![students[1].name.clone()]);
+        match_result
+            .placed
+            .insert("Reading".into(), vec![students[0].name.clone()]);
+
+        let blocking_pairs = find_blocking_pairs(&students, &categories, &match_result).unwrap();
+
+        assert_eq!(blocking_pairs, vec![]);
+    }
+
+    #[test]
+    fn test_find_blocking_pairs_priority() {
+        let (students, categories, mut match_result) = get_data();
+
+        match_result
+            .placed
+            .insert("Cooking".into(), vec![students[0].name.clone()]);
+        match_result
+            .placed
+            .insert("Reading".into(), vec![students[1].name.clone()]);
+
+        let blocking_pairs = find_blocking_pairs(&students, &categories, &match_result).unwrap();
+
+        assert_eq!(
+            blocking_pairs,
+            vec![BlockingPair {
+                student: "Kate".into(),
+                category: "Cooking".into(),
+                displaced: Some("Bert".into()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_blocking_pairs_open_spots() {
+        let (students, categories, mut match_result) = get_data();
+
+        // Bert is not placed, while there are open spots in reading.
+        match_result
+            .placed
+            .insert("Cooking".into(), vec![students[1].name.clone()]);
+        match_result.not_placable.push(students[0].name.clone());
+
+        let blocking_pairs = find_blocking_pairs(&students, &categories, &match_result).unwrap();
+
+        assert_eq!(
+            blocking_pairs,
+            vec![BlockingPair {
+                student: "Bert".into(),
+                category: "Reading".into(),
+                displaced: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_blocking_pairs_missing_lottery_number() {
+        let (students, categories, mut match_result) = get_data();
+
+        match_result.lottery_numbers.remove("Bert");
+
+        assert_eq!(
+            find_blocking_pairs(&students, &categories, &match_result),
+            Err(MatchError::InvalidInput(vec![Violation::UnknownStudent(
+                "Bert".into()
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_find_blocking_pairs_indifference_groups() {
+        let (mut students, categories, mut match_result) = get_data();
+
+        // Bert doesn't care whether he is placed in cooking or reading.
+        students[0] = Student::with_indifference_groups(
+            "Bert",
+            vec![vec![categories[0].clone(), categories[1].clone()]],
+            Vec::new(),
+        );
+        match_result
+            .placed
+            .insert("Reading".into(), vec![students[0].name.clone()]);
+
+        let blocking_pairs = find_blocking_pairs(&students, &categories, &match_result).unwrap();
+
+        assert_eq!(
+            blocking_pairs,
+            vec![
+                BlockingPair {
+                    student: "Kate".into(),
+                    category: "Cooking".into(),
+                    displaced: None,
+                },
+                BlockingPair {
+                    student: "Kate".into(),
+                    category: "Reading".into(),
+                    displaced: None,
+                },
+            ],
+            "Bert has open spots in cooking, but is as happy in reading"
+        );
+    }
+
+    #[test]
+    fn test_find_blocking_pairs_fixed_placement() {
+        let (students, mut categories, mut match_result) = get_data();
+
+        // Bert returns to cooking, so Kate can't take his spot.
+        categories[0] = categories[0].clone().with_fixed_placement("Bert");
+        match_result
+            .placed
+            .insert("Cooking".into(), vec![students[0].name.clone()]);
+        match_result
+            .placed
+            .insert("Reading".into(), vec![students[1].name.clone()]);
+
+        let blocking_pairs = find_blocking_pairs(&students, &categories, &match_result).unwrap();
+
+        assert_eq!(blocking_pairs, vec![]);
+    }
+}
@@ -0,0 +1,417 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Implements the Deferred Acceptance - Multiple Tie Break algorithm
+//!
+//! Unlike [`da_stb`](../da_stb/index.html), an independent lottery is drawn for every category.
+//! A student with a bad lot in one category can still have a good lot in another one.
+
+use super::da_stb::{assign_fallback, draw_order, place_students, select_students};
+use super::validation::{reject_fixed_placements, reject_groups};
+use super::{
+    category_indices, validate, Category, Fallback, MatchError, MatchResult, Matcher,
+    MultipleCategoryOptions, OrderedStudent, Priorities, Student, StudentsPerCategory,
+};
+use rand::prelude::*;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Deferred Acceptance - Multiple Tie Break mechanism
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DaMtb;
+
+impl Matcher for DaMtb {
+    fn match_unchecked(
+        &self,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+        mut rng: &mut dyn RngCore,
+    ) -> MatchResult {
+        match_students_unchecked(students, categories, fallback, &mut rng)
+    }
+
+    fn validate(&self, students: &[Student], categories: &[Category]) -> Result<(), MatchError> {
+        validate(students, categories)?;
+        reject_groups(students)?;
+        reject_fixed_placements(categories)?;
+        Ok(())
+    }
+}
+
+/// Match students to more than one category
+///
+/// Use this function when a single student can be placed simultaniously
+/// in more than one category. Students are never placed in two categories with overlapping
+/// time slots.
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::{Category, Student};
+/// use matchmaker::da_mtb::match_students_to_multiple_categories;
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// // Create categories
+/// let cooking = Category::new("Cooking", 10);
+/// let reading = Category::new("Reading", 10);
+/// let walking = Category::new("Walking", 5);
+///
+/// // Create student Bert
+/// // Bert wishes to be placed in category cooking or reading (in that order)
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// // Create student Suze
+/// // Suze wishes to be placed in category cooking or reading (in that order),
+/// // but does not wish to be placed in category walking
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::from([walking.clone()]),
+/// );
+///
+/// let mut rng = thread_rng();
+/// let categories = Vec::from([cooking, reading, walking]);
+///
+/// let match_result = match_students_to_multiple_categories(
+///     Vec::from([bert, suze]),
+///     &categories,
+///     &mut rng,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(match_result.placed.get("Cooking").unwrap().len(), 2);
+/// assert_eq!(match_result.placed.get("Reading").unwrap().len(), 2);
+/// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], or when students are part of a [`Group`] or categories have fixed
+/// placements, see [`Violation`].
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+/// [`Group`]: ../struct.Group.html
+/// [`Violation`]: ../enum.Violation.html
+pub fn match_students_to_multiple_categories(
+    students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    match_students_to_multiple_categories_with_options(
+        students,
+        categories,
+        &MultipleCategoryOptions::default(),
+        rng,
+    )
+}
+
+/// Match students to more than one category, limiting the categories a student can be
+/// placed in
+///
+/// Works like [`match_students_to_multiple_categories`], but students are placed in no more
+/// categories than their `max_categories` or `options.max_categories`, and with
+/// `options.preferences_only` only the first round can place a student in a category it
+/// didn't list.
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], or when students are part of a [`Group`] or categories have fixed
+/// placements, see [`Violation`].
+///
+/// [`match_students_to_multiple_categories`]: fn.match_students_to_multiple_categories.html
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+/// [`Group`]: ../struct.Group.html
+/// [`Violation`]: ../enum.Violation.html
+pub fn match_students_to_multiple_categories_with_options(
+    students: Vec<Student>,
+    categories: &[Category],
+    options: &MultipleCategoryOptions,
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    DaMtb.match_students_to_multiple_categories(students, categories, options, rng)
+}
+
+/// Match students to categories
+///
+/// Use this function if each student can only be placed in one category
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::da_mtb::match_students;
+/// use matchmaker::{Category, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// // Create categories
+/// let cooking = Category::new("Cooking", 1);
+/// let reading = Category::new("Reading", 1);
+///
+/// // Bert and Suze both wish to be placed in category cooking
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// let mut rng = thread_rng();
+/// let categories = Vec::from([cooking, reading]);
+///
+/// let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng).unwrap();
+///
+/// // The lottery of category cooking decides who is placed there
+/// assert_eq!(match_result.placed.get("Cooking").unwrap().len(), 1);
+/// assert_eq!(match_result.placed.get("Reading").unwrap().len(), 1);
+/// assert!(match_result.not_placable.is_empty());
+/// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], or when students are part of a [`Group`] or categories have fixed
+/// placements, see [`Violation`].
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+/// [`Group`]: ../struct.Group.html
+/// [`Violation`]: ../enum.Violation.html
+pub fn match_students(
+    students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    DaMtb.match_students(students, categories, rng)
+}
+
+pub(crate) fn match_students_unchecked(
+    students: &[Student],
+    categories: &[Category],
+    fallback: &Fallback,
+    mut rng: &mut impl Rng,
+) -> MatchResult {
+    let lotteries = draw_lotteries(students, categories, &mut rng);
+    let priorities = Priorities::new(students, categories);
+    let student_ids: HashMap<&str, usize> = students
+        .iter()
+        .enumerate()
+        .map(|(id, s)| (s.name.as_str(), id))
+        .collect();
+    // The single order is only used to decide who is assigned randomly first.
+    let mut unplaced_students = draw_order(students, categories, &mut rng);
+    let mut not_placable: Vec<OrderedStudent> = vec![];
+    let mut placed: StudentsPerCategory = vec![Vec::new(); categories.len()];
+    let mut waitlists: StudentsPerCategory = vec![Vec::new(); categories.len()];
+
+    // Place students in categories based on preferences
+    while !unplaced_students.is_empty() {
+        place_students(unplaced_students, &mut placed, &mut not_placable);
+        unplaced_students = truncate_categories(
+            &mut placed,
+            categories,
+            &priorities,
+            &lotteries,
+            &mut waitlists,
+        )
+    }
+
+    // Assign unplaced students to open spots by the fallback policy.
+    let not_placable = assign_fallback(not_placable, &mut placed, categories, fallback, &mut rng);
+
+    for (i, waitlist) in waitlists.iter_mut().enumerate() {
+        sort_by_priority(waitlist, &priorities, i, &lotteries[i]);
+    }
+
+    let mut match_result = MatchResult::from(placed, not_placable, waitlists, categories);
+
+    // Placements based on preferences are decided by the lottery of their category.
+    let indices = category_indices(categories);
+    for (name, placements) in match_result.placements.iter_mut() {
+        for placement in placements.iter_mut() {
+            if placement.preference_rank.is_some() {
+                let category = indices[placement.category.as_str()];
+                placement.lottery_number = lotteries[category][student_ids[name.as_str()]] + 1;
+            }
+        }
+    }
+
+    // Keep the lots a student drew in the categories it applied to or was placed in, so
+    // the result can be checked for blocking pairs.
+    for (id, student) in students.iter().enumerate() {
+        let placed_in = match_result
+            .placements
+            .get(&student.name)
+            .into_iter()
+            .flatten()
+            .map(|p| p.category.as_str());
+        let lots = student
+            .preferences
+            .iter()
+            .map(|c| c.name.as_str())
+            .chain(placed_in)
+            .map(|name| (name.to_string(), lotteries[indices[name]][id] + 1))
+            .collect();
+        match_result
+            .category_lottery_numbers
+            .insert(student.name.clone(), lots);
+    }
+
+    match_result
+}
+
+/// Draw a lottery number per student for every category
+///
+/// Returns the lottery numbers per category index, by the index of the student.
+fn draw_lotteries(
+    students: &[Student],
+    categories: &[Category],
+    mut rng: &mut impl Rng,
+) -> Vec<Vec<usize>> {
+    let mut lotteries = Vec::with_capacity(categories.len());
+
+    for _ in categories {
+        let mut ids: Vec<usize> = (0..students.len()).collect();
+        ids.shuffle(&mut rng);
+
+        let mut lottery = vec![0; students.len()];
+        for (i, id) in ids.into_iter().enumerate() {
+            lottery[id] = i;
+        }
+        lotteries.push(lottery);
+    }
+
+    lotteries
+}
+
+fn truncate_categories(
+    placed: &mut StudentsPerCategory,
+    categories: &[Category],
+    priorities: &Priorities,
+    lotteries: &[Vec<usize>],
+    waitlists: &mut StudentsPerCategory,
+) -> Vec<OrderedStudent> {
+    let mut unplaced_students: Vec<OrderedStudent> = Vec::new();
+
+    for (i, category) in categories.iter().enumerate() {
+        let placed_students = &mut placed[i];
+        if placed_students.len() > category.max_placements {
+            sort_by_priority(placed_students, priorities, i, &lotteries[i]);
+            let (accepted, rejected) = select_students(
+                std::mem::take(placed_students),
+                category,
+                category.max_placements,
+            );
+
+            waitlists[i].extend(rejected.iter().cloned());
+            unplaced_students.extend(rejected);
+            *placed_students = accepted;
+        }
+    }
+    unplaced_students
+}
+
+/// Sort students so the students with the highest priority for the category come first,
+/// using the lottery of the category to break ties
+fn sort_by_priority(
+    students: &mut [OrderedStudent],
+    priorities: &Priorities,
+    category: usize,
+    lottery: &[usize],
+) {
+    students.sort_by_key(|s| (Reverse(priorities.get(category, s)), lottery[s.id]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+    use std::collections::{BTreeSet, VecDeque};
+
+    #[test]
+    fn test_draw_lotteries() {
+        let mut rng = StepRng::new(2, 0);
+
+        let cooking = Category::new("Cooking", 1);
+        let reading = Category::new("Reading", 1);
+
+        let students = vec![
+            Student::new("Bert", VecDeque::new(), Vec::new()),
+            Student::new("Kate", VecDeque::new(), Vec::new()),
+            Student::new("Harry", VecDeque::new(), Vec::new()),
+        ];
+
+        let lotteries = draw_lotteries(&students, &[cooking, reading], &mut rng);
+
+        assert_eq!(lotteries.len(), 2);
+        for lottery in lotteries.iter() {
+            let mut numbers: Vec<usize> = lottery.clone();
+            numbers.sort_unstable();
+            assert_eq!(numbers, vec![0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn test_truncate_categories() {
+        let cooking = Category::new("Cooking", 1);
+        let reading = Category::new("Reading", 1);
+
+        let bert = OrderedStudent {
+            name: "Bert".into(),
+            id: 0,
+            preferences: VecDeque::from(vec![1]),
+            exclude: Vec::new(),
+            order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let kate = OrderedStudent {
+            name: "Kate".into(),
+            id: 1,
+            preferences: VecDeque::from(vec![1]),
+            exclude: Vec::new(),
+            order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+
+        // Kate has the best lot for cooking, even though Bert has the best single order.
+        let lotteries = vec![vec![1, 0], vec![0, 1]];
+
+        let mut placed = vec![vec![bert.clone(), kate.clone()], Vec::new()];
+
+        let categories = vec![cooking, reading];
+        let priorities = Priorities::new(&[], &categories);
+        let mut waitlists = vec![Vec::new(); 2];
+        let unplaced_students = truncate_categories(
+            &mut placed,
+            &categories,
+            &priorities,
+            &lotteries,
+            &mut waitlists,
+        );
+
+        assert_eq!(placed[0], vec![kate]);
+        assert_eq!(unplaced_students, vec![bert.clone()]);
+        assert_eq!(waitlists[0], vec![bert]);
+    }
+}
@@ -6,14 +6,38 @@
 
 //! Implements the Deferred Acceptance - Single Tie Break algorithm
 
-use super::{Category, MatchResult, OrderedStudent, Student};
+use super::validation::{reject_groups, reject_reserves};
+use super::{
+    category_indices, validate, Category, Fallback, MatchError, MatchResult, Matcher,
+    MultipleCategoryOptions, OrderedStudent, Priorities, ReserveOrder, Student,
+    StudentsPerCategory, Violation,
+};
 use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+use std::cmp::{Ordering, Reverse};
 use std::collections::HashMap;
 
+/// Deferred Acceptance - Single Tie Break mechanism
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DaStb;
+
+impl Matcher for DaStb {
+    fn match_unchecked(
+        &self,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+        mut rng: &mut dyn RngCore,
+    ) -> MatchResult {
+        match_students_unchecked(students, categories, fallback, &mut rng)
+    }
+}
+
 /// Match students to more than one category
 ///
 /// Use this function when a single student can be placed simultaniously
-/// in more than one category
+/// in more than one category. Students are never placed in two categories with overlapping
+/// time slots.
 ///
 /// # Example
 ///
@@ -51,25 +75,27 @@ use std::collections::HashMap;
 /// let match_result = match_students_to_multiple_categories(
 ///     Vec::from([bert, suze]),
 ///     &categories,
-///     &mut rng);
-///
-//// println!("Students matched to categories:");
-//// println!();
-//// for category in &categories {
-////     println!("{}:", &category.name);
-////     for student in match_result
-////         .placed
-////         .get(&category.name)
-////         .unwrap_or(&Vec::new())
-////     {
-////         println!(" - {}", &student.name);
-////     }
-//// }
-////
-//// if match_result.not_placable.is_empty() {
-////     println!();
-////     println!("All students could be placed.");
-//// }
+///     &mut rng,
+/// )
+/// .unwrap();
+///
+// println!("Students matched to categories:");
+// println!();
+// for category in &categories {
+//     println!("{}:", &category.name);
+//     for name in match_result
+//         .placed
+//         .get(&category.name)
+//         .unwrap_or(&Vec::new())
+//     {
+//         println!(" - {}", name);
+//     }
+// }
+//
+// if match_result.not_placable.is_empty() {
+//     println!();
+//     println!("All students could be placed.");
+// }
 /// ```
 ///
 /// The result will be something like this:
@@ -88,41 +114,214 @@ use std::collections::HashMap;
 ///
 /// All students could be placed.
 /// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`].
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
 pub fn match_students_to_multiple_categories(
-    mut students: Vec<Student>,
-    categories: &Vec<Category>,
-    mut rng: &mut impl Rng,
+    students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    match_students_to_multiple_categories_with_options(
+        students,
+        categories,
+        &MultipleCategoryOptions::default(),
+        rng,
+    )
+}
+
+/// Match students to more than one category, limiting the categories a student can be
+/// placed in
+///
+/// Works like [`match_students_to_multiple_categories`], but students are placed in no more
+/// categories than their `max_categories` or `options.max_categories`, and with
+/// `options.preferences_only` only the first round can place a student in a category it
+/// didn't list.
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::da_stb::match_students_to_multiple_categories_with_options;
+/// use matchmaker::{Category, MultipleCategoryOptions, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 10);
+/// let reading = Category::new("Reading", 10);
+/// let walking = Category::new("Walking", 5);
+///
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// // Suze only has time for one category
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// )
+/// .with_max_categories(1);
+///
+/// let mut rng = thread_rng();
+/// let categories = Vec::from([cooking, reading, walking]);
+/// let options = MultipleCategoryOptions {
+///     preferences_only: true,
+///     ..MultipleCategoryOptions::default()
+/// };
+///
+/// let match_result = match_students_to_multiple_categories_with_options(
+///     Vec::from([bert, suze]),
+///     &categories,
+///     &options,
+///     &mut rng,
+/// )
+/// .unwrap();
+///
+/// // Bert is placed in cooking and reading, but not in walking
+/// assert_eq!(match_result.placements["Bert"].len(), 2);
+/// assert!(match_result.placed.get("Walking").is_none());
+/// assert_eq!(match_result.placements["Suze"].len(), 1);
+/// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`].
+///
+/// [`match_students_to_multiple_categories`]: fn.match_students_to_multiple_categories.html
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+pub fn match_students_to_multiple_categories_with_options(
+    students: Vec<Student>,
+    categories: &[Category],
+    options: &MultipleCategoryOptions,
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    DaStb.match_students_to_multiple_categories(students, categories, options, rng)
+}
+
+/// Repeatedly run `match_students` until no more spots are filled, so a single student
+/// can be placed in more than one category, but never in two categories at the same time
+pub(crate) fn match_multiple_categories<R: Rng>(
+    original_students: &[Student],
+    categories: &[Category],
+    options: &MultipleCategoryOptions,
+    rng: &mut R,
+    match_students: impl Fn(&[Student], &[Category], &mut R) -> MatchResult,
 ) -> MatchResult {
-    let mut match_result: MatchResult = MatchResult {
-        placed: HashMap::new(),
-        not_placable: Vec::new(),
-    };
-    let mut categories = categories.clone();
+    let mut match_result = MatchResult::default();
+    let mut students = original_students.to_vec();
+    let mut categories = categories.to_vec();
     let mut spots_available = categories.iter().map(|c| c.max_placements).sum();
     let mut previous_spots_available = usize::MAX;
     let mut first_round = true;
 
+    // Students exclude copies of the categories without their priorities, so the priorities
+    // are not copied for every student.
+    let excluded_categories: Vec<Category> = categories
+        .iter()
+        .map(|c| {
+            let mut category = c.clone();
+            category.priorities.clear();
+            category
+        })
+        .collect();
+
+    // Look up students by name once, instead of searching them for every placement. The
+    // students of a round are found by their position, which changes when students leave.
+    let student_indices: HashMap<&str, usize> = original_students
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.name.as_str(), i))
+        .collect();
+    let mut positions: Vec<Option<usize>> = (0..students.len()).map(Some).collect();
+    let mut placement_counts: Vec<usize> = vec![0; students.len()];
+
     // Keep going until there are no more spots or until no more new spots are filled.
     while spots_available > 0 && previous_spots_available > spots_available {
-        let mut new_match_result = match_students(students.clone(), &categories, &mut rng);
+        // Leave out students that are placed in as many categories as they wish.
+        let student_count = students.len();
+        students.retain(|s| match s.max_categories.or(options.max_categories) {
+            Some(max_categories) => {
+                placement_counts[student_indices[s.name.as_str()]] < max_categories
+            }
+            None => true,
+        });
+        if students.len() < student_count {
+            positions = vec![None; original_students.len()];
+            for (position, student) in students.iter().enumerate() {
+                positions[student_indices[student.name.as_str()]] = Some(position);
+            }
+        }
+
+        let mut new_match_result = match_students(&students, &categories, rng);
 
         // Merge match_result.placable and prepare categories and students for next round.
-        for category in categories.iter_mut() {
+        for (i, category) in categories.iter_mut().enumerate() {
+            // Fixed placements are made in the first round.
+            category.fixed_placements.clear();
+
             if let Some(placed_students) = new_match_result.placed.remove(&category.name) {
                 // Update the category with the amount of spots left.
                 category.max_placements -= placed_students.len();
 
+                // Students with the attribute of a reserve take its seats, which are not
+                // reserved again in the next round.
+                let mut counted = vec![false; placed_students.len()];
+                for reserve in category.reserves.iter_mut() {
+                    for (name, counted) in placed_students.iter().zip(counted.iter_mut()) {
+                        let has_attribute = student_indices.get(name.as_str()).is_some_and(|&id| {
+                            original_students[id]
+                                .attributes
+                                .contains(&reserve.attribute)
+                        });
+                        if reserve.seats > 0 && !*counted && has_attribute {
+                            reserve.seats -= 1;
+                            *counted = true;
+                        }
+                    }
+                }
+
                 // Add this category to match_result
-                if match_result.placed.get(&category.name).is_none() {
+                if !match_result.placed.contains_key(&category.name) {
                     match_result
                         .placed
                         .insert(category.name.clone(), Vec::new());
                 }
 
+                // Categories at the same time as this category.
+                let overlapping: Vec<&Category> = excluded_categories
+                    .iter()
+                    .filter(|c| c.name != category.name && c.overlaps(category))
+                    .collect();
+
                 for ps in placed_students {
-                    for student in students.iter_mut().filter(|s| s.name == ps.name) {
+                    if let Some(&id) = student_indices.get(ps.as_str()) {
+                        placement_counts[id] += 1;
+                    }
+                    if let Some(position) = student_indices
+                        .get(ps.as_str())
+                        .and_then(|&id| positions[id])
+                    {
+                        let student = &mut students[position];
+
                         // Make sure students placed in this category can't be assigned to it in the next round.
-                        student.exclude.push(category.clone());
+                        student.exclude.push(excluded_categories[i].clone());
+                        student.remove_preference(&category.name);
+
+                        // Nor to a category at the same time.
+                        for &other in overlapping.iter() {
+                            if !student.exclude.contains(other) {
+                                student.exclude.push(other.clone());
+                            }
+                            student.remove_preference(&other.name);
+                        }
                     }
 
                     // Add student to match_result (we can safely unwrap here, because we just added the category).
@@ -135,19 +334,131 @@ pub fn match_students_to_multiple_categories(
             }
         }
 
-        // Only use not_placable result from first round. Students later not placed are placed the first time.
+        // Extend the waitlists with students that were not on them yet.
+        for (name, waitlist) in new_match_result.waitlists {
+            let merged_waitlist = match_result.waitlists.entry(name).or_default();
+            for student in waitlist {
+                if !merged_waitlist.contains(&student) {
+                    merged_waitlist.push(student);
+                }
+            }
+        }
+
+        for (name, placements) in new_match_result.placements {
+            match_result
+                .placements
+                .entry(name)
+                .or_default()
+                .extend(placements);
+        }
+
+        // Only use not_placable result and lottery numbers from first round. Students later not placed are placed the first time.
         if first_round {
             match_result.not_placable = new_match_result.not_placable;
+            match_result.lottery_numbers = new_match_result.lottery_numbers;
+            match_result.category_lottery_numbers = new_match_result.category_lottery_numbers;
             first_round = false;
+
+            // Exclude the categories students didn't ask for from the next rounds.
+            if options.preferences_only {
+                for student in students.iter_mut() {
+                    let unlisted: Vec<Category> = excluded_categories
+                        .iter()
+                        .filter(|c| {
+                            !student.preferences.contains(c) && !student.exclude.contains(c)
+                        })
+                        .cloned()
+                        .collect();
+                    student.exclude.extend(unlisted);
+                }
+            }
         }
 
         previous_spots_available = spots_available;
         spots_available = categories.iter().map(|c| c.max_placements).sum();
     }
 
+    rank_by_preferences(&mut match_result, original_students);
     match_result
 }
 
+/// Match students and cancel categories that don't reach their minimum number of students
+///
+/// Categories are cancelled one at a time, starting with the category with the fewest
+/// students. After each cancellation the students are matched again with the same lottery.
+pub(crate) fn match_with_minimums(
+    mut students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut dyn RngCore,
+    match_students: impl Fn(&[Student], &[Category], &mut dyn RngCore) -> MatchResult,
+) -> MatchResult {
+    if categories.iter().all(|c| c.min_placements == 0) {
+        return match_students(&students, categories, rng);
+    }
+
+    // Seed every run the same way, so every run draws the same lottery.
+    let mut seed = [0; 32];
+    rng.fill_bytes(&mut seed);
+
+    let original_students = students.clone();
+    let mut categories = categories.to_vec();
+    let mut cancelled: Vec<String> = Vec::new();
+
+    loop {
+        let mut match_result =
+            match_students(&students, &categories, &mut ChaCha20Rng::from_seed(seed));
+
+        // Fixed placements are never undone, so their categories go ahead.
+        let under_enrolled = categories
+            .iter()
+            .filter(|c| !cancelled.contains(&c.name) && c.fixed_placements.is_empty())
+            .map(|c| (match_result.placed.get(&c.name).map_or(0, Vec::len), c))
+            .filter(|(placed, c)| *placed < c.min_placements)
+            .min_by_key(|(placed, _)| *placed)
+            .map(|(_, c)| c.name.clone());
+
+        match under_enrolled {
+            Some(name) => {
+                cancel_category(&mut students, &mut categories, &name);
+                cancelled.push(name);
+            }
+            None => {
+                for name in cancelled.iter() {
+                    match_result.placed.remove(name);
+                    match_result.waitlists.remove(name);
+                }
+
+                rank_by_preferences(&mut match_result, &original_students);
+                match_result.cancelled = cancelled;
+                return match_result;
+            }
+        }
+    }
+}
+
+/// Rank placements by the preferences the students gave, cancelled categories included
+fn rank_by_preferences(match_result: &mut MatchResult, students: &[Student]) {
+    for student in students {
+        if let Some(placements) = match_result.placements.get_mut(&student.name) {
+            for placement in placements.iter_mut() {
+                if placement.preference_rank.is_some() {
+                    placement.preference_rank = student.preference_rank(&placement.category);
+                }
+            }
+        }
+    }
+}
+
+/// Close a category and remove it from the preferences of the students
+fn cancel_category(students: &mut [Student], categories: &mut [Category], name: &str) {
+    for category in categories.iter_mut().filter(|c| c.name == name) {
+        category.max_placements = 0;
+    }
+    for student in students.iter_mut() {
+        student.remove_preference(name);
+    }
+}
+
 /// Match students to categories
 ///
 /// Use this function if each student can only be placed in one category
@@ -185,18 +496,18 @@ pub fn match_students_to_multiple_categories(
 /// let mut rng = thread_rng();
 /// let categories = Vec::from([cooking, reading, walking]);
 ///
-/// let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng);
+/// let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng).unwrap();
 ///
 /// println!("Students matched to categories:");
 /// println!();
 /// for category in &categories {
 ///     println!("{}:", &category.name);
-///     for student in match_result
+///     for name in match_result
 ///         .placed
 ///         .get(&category.name)
 ///         .unwrap_or(&Vec::new())
 ///     {
-///         println!(" - {}", &student.name);
+///         println!(" - {}", name);
 ///     }
 /// }
 ///
@@ -211,124 +522,1028 @@ pub fn match_students_to_multiple_categories(
 /// ```text
 /// Students matched to categories:
 ///
-/// Cooking:
-///  - Bert
-/// Reading:
-///  - Suze
-/// Walking:
+/// Cooking:
+///  - Bert
+/// Reading:
+///  - Suze
+/// Walking:
+///
+/// All students could be placed.
+/// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`].
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+pub fn match_students(
+    students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    DaStb.match_students(students, categories, rng)
+}
+
+/// Match students to categories, placing students that can't be placed in their preferences
+/// by a fallback policy
+///
+/// [`match_students`] places these students in a random open category, even one they did not
+/// list. The policy is recorded in the result, and reused by [`withdraw_students`].
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::da_stb::match_students_with_fallback;
+/// use matchmaker::{Category, Fallback, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let reading = Category::new("Reading", 1);
+///
+/// let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new());
+/// let suze = Student::new("Suze", VecDeque::from(vec![cooking.clone()]), Vec::new());
+///
+/// let mut rng = thread_rng();
+/// let students = Vec::from([bert, suze]);
+/// let categories = Vec::from([cooking, reading]);
+///
+/// // Reading is not a valid offer for whoever doesn't get cooking
+/// let match_result =
+///     match_students_with_fallback(students, &categories, &Fallback::None, &mut rng).unwrap();
+///
+/// assert_eq!(match_result.not_placable.len(), 1);
+/// assert!(match_result.placed.get("Reading").is_none());
+/// assert_eq!(match_result.fallback, Fallback::None);
+/// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], or when the policy refers to students or categories that are not part of
+/// the match.
+///
+/// [`match_students`]: fn.match_students.html
+/// [`withdraw_students`]: fn.withdraw_students.html
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+pub fn match_students_with_fallback(
+    students: Vec<Student>,
+    categories: &[Category],
+    fallback: &Fallback,
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    DaStb.match_students_with_fallback(students, categories, fallback, rng)
+}
+
+pub(crate) fn match_students_unchecked(
+    students: &[Student],
+    categories: &[Category],
+    fallback: &Fallback,
+    mut rng: &mut impl Rng,
+) -> MatchResult {
+    let unplaced_students = draw_order(students, categories, &mut rng);
+    let priorities = Priorities::new(students, categories);
+    let (unplaced_students, fixed, capacities) = take_fixed(unplaced_students, categories);
+    let (mut placed, not_placable, waitlists) =
+        defer_acceptance(unplaced_students, categories, &priorities, &capacities);
+    add_fixed(&mut placed, fixed);
+
+    // Assign unplaced students to open spots by the fallback policy.
+    let not_placable = assign_fallback(not_placable, &mut placed, categories, fallback, &mut rng);
+
+    let mut match_result = MatchResult::from(placed, not_placable, waitlists, categories);
+    mark_fixed(&mut match_result, categories);
+    match_result
+}
+
+/// Take the students with a fixed placement out of the match
+///
+/// Returns the other students, the students with a fixed placement per category index, and
+/// the number of seats that are left per category index.
+pub(crate) fn take_fixed(
+    students: Vec<OrderedStudent>,
+    categories: &[Category],
+) -> (Vec<OrderedStudent>, StudentsPerCategory, Vec<usize>) {
+    let mut fixed_categories: HashMap<&str, usize> = HashMap::new();
+    for (i, category) in categories.iter().enumerate() {
+        for name in category.fixed_placements.iter() {
+            fixed_categories.entry(name.as_str()).or_insert(i);
+        }
+    }
+
+    let mut unplaced_students: Vec<OrderedStudent> = Vec::with_capacity(students.len());
+    let mut fixed: StudentsPerCategory = vec![Vec::new(); categories.len()];
+
+    for mut student in students.into_iter() {
+        match fixed_categories.get(student.name.as_str()) {
+            Some(&category) => {
+                student.rank = student
+                    .preferences
+                    .iter()
+                    .position(|&c| c == category)
+                    .map(|i| student.preference_ranks.get(i).copied().unwrap_or(i + 1));
+                fixed[category].push(student);
+            }
+            None => unplaced_students.push(student),
+        }
+    }
+
+    let capacities = categories
+        .iter()
+        .zip(fixed.iter())
+        .map(|(c, students)| c.max_placements.saturating_sub(students.len()))
+        .collect();
+
+    (unplaced_students, fixed, capacities)
+}
+
+/// Place the students with a fixed placement in front of the other students
+pub(crate) fn add_fixed(placed: &mut StudentsPerCategory, fixed: StudentsPerCategory) {
+    for (placed_students, students) in placed.iter_mut().zip(fixed) {
+        placed_students.splice(0..0, students);
+    }
+}
+
+/// Mark the placements that were fixed before the match
+pub(crate) fn mark_fixed(match_result: &mut MatchResult, categories: &[Category]) {
+    for category in categories {
+        for name in category.fixed_placements.iter() {
+            if let Some(placements) = match_result.placements.get_mut(name) {
+                for placement in placements.iter_mut() {
+                    if placement.category == category.name {
+                        placement.fixed = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Release the spots of students that withdraw after a match
+///
+/// The lottery numbers from the original match are reused, so nobody's lot changes. Freed spots
+/// go to waitlisted students, which may free other spots in turn. Students only move to a
+/// category they prefer, and students that were placed by the fallback policy keep their spot
+/// unless they can move to one of their preferences. Students that are still unplaced are
+/// assigned to the spots left by the fallback policy recorded in the original result.
+///
+/// Use this function with the result of [`match_students`].
+///
+/// # Arguments
+///
+/// * `students` - Students of the original match, with their original preferences
+/// * `categories` - Categories of the original match
+/// * `match_result` - Result of the original match
+/// * `withdrawn` - Names of the students that withdraw
+/// * `rng` - Random number generator used to assign students randomly
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], or when a student is not part of the original match.
+///
+/// [`match_students`]: fn.match_students.html
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::da_stb::{match_students, withdraw_students};
+/// use matchmaker::{Category, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let reading = Category::new("Reading", 1);
+///
+/// // Bert and Suze both wish to be placed in category cooking
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// let mut rng = thread_rng();
+/// let students = Vec::from([bert, suze]);
+/// let categories = Vec::from([cooking, reading]);
+///
+/// let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+///
+/// // Whoever got cooking withdraws, so the other student moves up
+/// let withdrawn = match_result.placed.get("Cooking").unwrap()[0].clone();
+/// let match_result = withdraw_students(
+///     students,
+///     &categories,
+///     &match_result,
+///     &[withdrawn.clone()],
+///     &mut rng,
+/// )
+/// .unwrap();
+///
+/// assert_ne!(match_result.placed.get("Cooking").unwrap()[0], withdrawn);
+/// assert!(match_result.placed.get("Reading").is_none());
+/// ```
+pub fn withdraw_students(
+    mut students: Vec<Student>,
+    categories: &[Category],
+    match_result: &MatchResult,
+    withdrawn: &[String],
+    mut rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    validate(&students, categories)?;
+    students.iter_mut().for_each(Student::clear_priorities);
+
+    // Categories that were cancelled stay cancelled.
+    let original_students = students.clone();
+    let mut categories = categories.to_vec();
+    for name in match_result.cancelled.iter() {
+        cancel_category(&mut students, &mut categories, name);
+    }
+
+    let mut violations: Vec<Violation> = Vec::new();
+    for name in withdrawn {
+        if !match_result.lottery_numbers.contains_key(name) {
+            violations.push(Violation::UnknownStudent(name.clone()));
+        }
+    }
+
+    // Restore the original lottery, leaving out the students that withdraw.
+    let indices = category_indices(&categories);
+    let mut unplaced_students: Vec<OrderedStudent> = Vec::with_capacity(students.len());
+    for (id, student) in students.iter().enumerate() {
+        if withdrawn.contains(&student.name) {
+            continue;
+        }
+        match match_result.lottery_numbers.get(&student.name) {
+            Some(lottery_number) => {
+                let mut student = OrderedStudent::new(student, id, lottery_number - 1, &indices);
+
+                // Break ties in favour of the current placement, so nobody ends up in a
+                // group they like less.
+                if let Some(&category) = match_result
+                    .placements
+                    .get(&student.name)
+                    .and_then(|p| p.iter().find(|p| p.preference_rank.is_some()))
+                    .and_then(|placement| indices.get(placement.category.as_str()))
+                {
+                    student.prefer_within_group(category);
+                }
+                unplaced_students.push(student);
+            }
+            None => violations.push(Violation::UnknownStudent(student.name.clone())),
+        }
+    }
+
+    if !violations.is_empty() {
+        return Err(MatchError::InvalidInput(violations));
+    }
+
+    let (unplaced_students, fixed, capacities) = take_fixed(unplaced_students, &categories);
+    let priorities = Priorities::new(&students, &categories);
+    let (mut placed, not_placable, waitlists) =
+        defer_acceptance(unplaced_students, &categories, &priorities, &capacities);
+    add_fixed(&mut placed, fixed);
+
+    // Students that were placed by the fallback policy and did not move up keep their spot.
+    let mut still_not_placable: Vec<OrderedStudent> = Vec::with_capacity(not_placable.len());
+    for student in not_placable.into_iter() {
+        let random_placement = match_result
+            .placements
+            .get(&student.name)
+            .and_then(|p| {
+                p.iter()
+                    .find(|placement| placement.preference_rank.is_none())
+            })
+            .and_then(|placement| indices.get(placement.category.as_str()));
+
+        match random_placement {
+            Some(&category) => placed[category].push(OrderedStudent {
+                rank: None,
+                ..student
+            }),
+            None => still_not_placable.push(student),
+        }
+    }
+
+    // Assign unplaced students to open spots by the fallback policy of the original match.
+    let not_placable = assign_fallback(
+        still_not_placable,
+        &mut placed,
+        &categories,
+        &match_result.fallback,
+        &mut rng,
+    );
+
+    let mut new_match_result = MatchResult::from(placed, not_placable, waitlists, &categories);
+    new_match_result.fallback = match_result.fallback.clone();
+    mark_fixed(&mut new_match_result, &categories);
+    for name in match_result.cancelled.iter() {
+        new_match_result.placed.remove(name);
+        new_match_result.waitlists.remove(name);
+    }
+    rank_by_preferences(&mut new_match_result, &original_students);
+    new_match_result.cancelled = match_result.cancelled.clone();
+
+    Ok(new_match_result)
+}
+
+/// Let students trade spots they lost to a tie in priority, keeping the result stable
+///
+/// A single lottery breaks ties in priority the same way in every category, which can leave
+/// students worse off than needed: Bert may be placed in reading while preferring cooking,
+/// where Suze is placed while preferring reading, and both lost their first choice to a tie.
+/// A stable improvement cycle (Erdil and Ergin) moves every student in the cycle to the spot
+/// of the next one, where each student prefers the category it moves to, and has the highest
+/// priority of all students that prefer that category to their placement. Cycles are
+/// executed until none are left, so no other result that is stable under the priorities of
+/// the categories places every student at least as well.
+///
+/// Students keep their lottery numbers. As the lottery no longer decides every tie, the
+/// result can have [blocking pairs](../analysis/fn.find_blocking_pairs.html) by lot, but not
+/// by priority. Students with a fixed placement never move.
+///
+/// Use this function with the result of [`match_students`] or [`match_students_with_fallback`].
+///
+/// # Arguments
+///
+/// * `students` - Students of the match, with their original preferences
+/// * `categories` - Categories of the match
+/// * `match_result` - Result of the match
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], when a student is not part of the match, or when students are part of a
+/// [`Group`] or categories have reserved seats, which trading spots can't keep.
+///
+/// [`match_students`]: fn.match_students.html
+/// [`match_students_with_fallback`]: fn.match_students_with_fallback.html
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+/// [`Group`]: ../struct.Group.html
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::da_stb::{improve_placements, match_students};
+/// use matchmaker::{Category, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// // Bert and Suze each have priority in the category the other prefers
+/// let cooking = Category::new("Cooking", 1).with_priority("Suze", 1);
+/// let reading = Category::new("Reading", 1).with_priority("Bert", 1);
+/// let walking = Category::new("Walking", 1);
+///
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![reading.clone(), cooking.clone()]),
+///     Vec::new(),
+/// );
+/// let kate = Student::new(
+///     "Kate",
+///     VecDeque::from(vec![reading.clone(), walking.clone()]),
+///     Vec::new(),
+/// );
+///
+/// let mut rng = thread_rng();
+/// let students = Vec::from([bert, suze, kate]);
+/// let categories = Vec::from([cooking, reading, walking]);
+///
+/// // When Kate draws a better lot than Suze, Bert and Suze end up in the category they
+/// // like least
+/// let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+/// let match_result = improve_placements(students, &categories, &match_result).unwrap();
+///
+/// assert_eq!(match_result.placed["Cooking"][0], "Bert");
+/// assert_eq!(match_result.placed["Reading"][0], "Suze");
+/// assert_eq!(match_result.placed["Walking"][0], "Kate");
+/// ```
+pub fn improve_placements(
+    students: Vec<Student>,
+    categories: &[Category],
+    match_result: &MatchResult,
+) -> Result<MatchResult, MatchError> {
+    validate(&students, categories)?;
+    reject_groups(&students)?;
+    reject_reserves(categories)?;
+
+    let violations: Vec<Violation> = students
+        .iter()
+        .filter(|s| !match_result.lottery_numbers.contains_key(&s.name))
+        .map(|s| Violation::UnknownStudent(s.name.clone()))
+        .collect();
+    if !violations.is_empty() {
+        return Err(MatchError::InvalidInput(violations));
+    }
+
+    let indices = category_indices(categories);
+    let ids: HashMap<&str, usize> = students
+        .iter()
+        .enumerate()
+        .map(|(id, s)| (s.name.as_str(), id))
+        .collect();
+
+    // Category index, preference rank and priority of every preference of every student.
+    let preferences: Vec<Vec<(usize, usize, usize)>> = students
+        .iter()
+        .map(|s| {
+            s.preferences
+                .iter()
+                .zip(s.preference_ranks())
+                .filter_map(|(c, rank)| {
+                    indices
+                        .get(c.name.as_str())
+                        .map(|&i| (i, rank, categories[i].priority(&s.name)))
+                })
+                .collect()
+        })
+        .collect();
+    let mut movable = vec![true; students.len()];
+    for name in categories.iter().flat_map(|c| c.fixed_placements.iter()) {
+        if let Some(&id) = ids.get(name.as_str()) {
+            movable[id] = false;
+        }
+    }
+
+    // The spots of every category hold the students in the order of the result, or `None`
+    // for students that are not part of the match.
+    let mut spots: Vec<Vec<Option<usize>>> = vec![Vec::new(); categories.len()];
+    let mut placements: Vec<Option<usize>> = vec![None; students.len()];
+    for (name, placed_students) in match_result.placed.iter() {
+        if let Some(&category) = indices.get(name.as_str()) {
+            for name in placed_students {
+                let id = ids.get(name.as_str()).copied();
+                if let Some(id) = id {
+                    placements[id] = Some(category);
+                }
+                spots[category].push(id);
+            }
+        }
+    }
+    let original_placements = placements.clone();
+
+    // Rank of the placement of every student, where placements outside the preferences rank
+    // below every preference.
+    let rank = |id: usize, category: Option<usize>| {
+        preferences[id]
+            .iter()
+            .find(|p| Some(p.0) == category)
+            .map_or(usize::MAX, |p| p.1)
+    };
+    let mut ranks: Vec<usize> = (0..students.len())
+        .map(|id| rank(id, placements[id]))
+        .collect();
+
+    loop {
+        // Students point to the categories they prefer to their placement, where they have
+        // the highest priority of all students that do.
+        let mut highest: Vec<Option<usize>> = vec![None; categories.len()];
+        for id in (0..students.len()).filter(|&id| movable[id]) {
+            for &(category, _, priority) in preferences[id].iter().take_while(|p| p.1 < ranks[id]) {
+                highest[category] = highest[category].max(Some(priority));
+            }
+        }
+        let desired: Vec<Vec<usize>> = (0..students.len())
+            .map(|id| {
+                preferences[id]
+                    .iter()
+                    .take_while(|p| movable[id] && p.1 < ranks[id])
+                    .filter(|&&(category, _, priority)| highest[category] == Some(priority))
+                    .map(|&(category, _, _)| category)
+                    .collect()
+            })
+            .collect();
+        let occupants: Vec<Vec<usize>> = spots
+            .iter()
+            .map(|s| {
+                s.iter()
+                    .flatten()
+                    .copied()
+                    .filter(|&id| movable[id])
+                    .collect()
+            })
+            .collect();
+
+        let moves = find_improvement_cycles(&desired, &occupants);
+        if moves.is_empty() {
+            break;
+        }
+        for (student, category, displaced) in moves {
+            if let Some(spot) = spots[category].iter_mut().find(|s| **s == Some(displaced)) {
+                *spot = Some(student);
+            }
+            placements[student] = Some(category);
+            ranks[student] = rank(student, Some(category));
+        }
+    }
+
+    let mut new_match_result = match_result.clone();
+    for (name, placed_students) in new_match_result.placed.iter_mut() {
+        let category = match indices.get(name.as_str()) {
+            Some(&category) => category,
+            None => continue,
+        };
+        for (student, &id) in placed_students.iter_mut().zip(spots[category].iter()) {
+            if let Some(id) = id {
+                if placements[id] != original_placements[id] {
+                    *student = students[id].name.clone();
+                }
+            }
+        }
+    }
+
+    // Moved students leave the waitlists of the categories they no longer prefer.
+    for (id, student) in students.iter().enumerate() {
+        let (from, to) = match (original_placements[id], placements[id]) {
+            (Some(from), Some(to)) if from != to => (&categories[from].name, &categories[to].name),
+            _ => continue,
+        };
+        if let Some(placement) = new_match_result
+            .placements
+            .get_mut(&student.name)
+            .and_then(|p| p.iter_mut().find(|p| &p.category == from))
+        {
+            placement.category = to.clone();
+            placement.preference_rank = student.preference_rank(to);
+        }
+        for (name, waitlist) in new_match_result.waitlists.iter_mut() {
+            if student.preference_rank(name) >= student.preference_rank(to) {
+                waitlist.retain(|name| name != &student.name);
+            }
+        }
+    }
+    new_match_result
+        .waitlists
+        .retain(|_, waitlist| !waitlist.is_empty());
+
+    Ok(new_match_result)
+}
+
+/// Whether a student or category is being searched for a cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    New,
+    Open,
+    Done,
+}
+
+/// Find cycles of students that point to the categories they desire, and categories that
+/// point to the students placed in them, where no two cycles share a student or category
+///
+/// Searches the students in order, and follows the desired categories and their students in
+/// order. Returns a move for every student in a cycle: the student, the category it moves to
+/// and the student whose spot it takes.
+fn find_improvement_cycles(
+    desired: &[Vec<usize>],
+    occupants: &[Vec<usize>],
+) -> Vec<(usize, usize, usize)> {
+    // Students are nodes `0..student_count`, categories follow them.
+    let student_count = desired.len();
+    let next = |node: usize, edge: usize| {
+        if node < student_count {
+            desired[node].get(edge).map(|&c| student_count + c)
+        } else {
+            occupants[node - student_count].get(edge).copied()
+        }
+    };
+
+    let mut visits = vec![Visit::New; student_count + occupants.len()];
+    let mut moves: Vec<(usize, usize, usize)> = Vec::new();
+
+    for start in 0..student_count {
+        if visits[start] != Visit::New {
+            continue;
+        }
+        visits[start] = Visit::Open;
+        let mut path: Vec<(usize, usize)> = vec![(start, 0)];
+
+        while let Some(&(node, edge)) = path.last() {
+            let last = path.len() - 1;
+            path[last].1 += 1;
+            let target = match next(node, edge) {
+                Some(target) => target,
+                None => {
+                    visits[node] = Visit::Done;
+                    path.pop();
+                    continue;
+                }
+            };
+
+            match visits[target] {
+                Visit::New => {
+                    visits[target] = Visit::Open;
+                    path.push((target, 0));
+                }
+                // The path leads back to itself, so the nodes since the target form a cycle,
+                // which takes them out of the search.
+                Visit::Open => {
+                    let position = path.iter().position(|&(n, _)| n == target).unwrap_or(0);
+                    let cycle: Vec<usize> = path.drain(position..).map(|(n, _)| n).collect();
+                    for (i, &node) in cycle.iter().enumerate() {
+                        visits[node] = Visit::Done;
+                        if node < student_count {
+                            let category = cycle[(i + 1) % cycle.len()];
+                            let displaced = cycle[(i + 2) % cycle.len()];
+                            moves.push((node, category - student_count, displaced));
+                        }
+                    }
+                }
+                Visit::Done => {}
+            }
+        }
+    }
+
+    moves
+}
+
+/// Place students based on their preferences until no category holds more students than it can
 ///
-/// All students could be placed.
-/// ```
-pub fn match_students(
-    students: Vec<Student>,
-    categories: &Vec<Category>,
-    mut rng: &mut impl Rng,
-) -> MatchResult {
-    let mut unplaced_students = draw_order(students, &mut rng);
+/// Returns the placed students per category index, the students that could not be placed
+/// and the waitlists per category index.
+fn defer_acceptance(
+    mut unplaced_students: Vec<OrderedStudent>,
+    categories: &[Category],
+    priorities: &Priorities,
+    capacities: &[usize],
+) -> (
+    StudentsPerCategory,
+    Vec<OrderedStudent>,
+    StudentsPerCategory,
+) {
     let mut not_placable: Vec<OrderedStudent> = vec![];
-    let mut placed: HashMap<String, Vec<OrderedStudent>> = HashMap::new();
+    let mut placed: StudentsPerCategory = vec![Vec::new(); categories.len()];
+    let mut waitlists: StudentsPerCategory = vec![Vec::new(); categories.len()];
+
+    let soft_group_members: Vec<OrderedStudent> = unplaced_students
+        .iter()
+        .filter(|s| matches!(&s.group, Some(group) if !group.hard))
+        .cloned()
+        .collect();
 
     // Place students in categories based on preferences
     while !unplaced_students.is_empty() {
         place_students(unplaced_students, &mut placed, &mut not_placable);
-        unplaced_students = truncate_categories(&mut placed, &categories)
+        unplaced_students = truncate_categories(
+            &mut placed,
+            categories,
+            priorities,
+            capacities,
+            &mut waitlists,
+        )
+    }
+
+    // Members of soft groups that could not be placed together try again on their own, for
+    // the spots that are left.
+    let (split_members, still_not_placable): (Vec<OrderedStudent>, Vec<OrderedStudent>) =
+        not_placable
+            .into_iter()
+            .partition(|s| matches!(&s.group, Some(group) if !group.hard));
+    not_placable = still_not_placable;
+
+    if !split_members.is_empty() {
+        let mut unplaced_students: Vec<OrderedStudent> = soft_group_members
+            .into_iter()
+            .filter(|s| split_members.iter().any(|m| m.id == s.id))
+            .map(|s| OrderedStudent { group: None, ..s })
+            .collect();
+        let open_capacities: Vec<usize> = capacities
+            .iter()
+            .zip(placed.iter())
+            .map(|(capacity, students)| capacity - students.len())
+            .collect();
+        let mut split_placed: StudentsPerCategory = vec![Vec::new(); categories.len()];
+        let mut split_waitlists: StudentsPerCategory = vec![Vec::new(); categories.len()];
+
+        while !unplaced_students.is_empty() {
+            place_students(unplaced_students, &mut split_placed, &mut not_placable);
+            unplaced_students = truncate_categories(
+                &mut split_placed,
+                categories,
+                priorities,
+                &open_capacities,
+                &mut split_waitlists,
+            )
+        }
+
+        for (placed_students, students) in placed.iter_mut().zip(split_placed) {
+            placed_students.extend(students);
+        }
+        for (waitlist, students) in waitlists.iter_mut().zip(split_waitlists) {
+            for student in students {
+                if !waitlist.iter().any(|s| s.id == student.id) {
+                    waitlist.push(student);
+                }
+            }
+        }
     }
 
-    // Randomly assign unplaced students among open spots in categories.
-    let not_placable = assign_random(not_placable, &mut placed, &categories, &mut rng);
+    for (category, waitlist) in waitlists.iter_mut().enumerate() {
+        sort_by_priority(waitlist, priorities, category);
+    }
 
-    MatchResult::from(placed, not_placable)
+    (placed, not_placable, waitlists)
 }
 
-fn draw_order(mut students: Vec<Student>, mut rng: &mut impl Rng) -> Vec<OrderedStudent> {
-    students.shuffle(&mut rng);
+/// Draw the lottery and prepare the students for the match
+///
+/// The names of the categories are looked up once here, so the students refer to the
+/// categories by their index from now on.
+pub(crate) fn draw_order(
+    students: &[Student],
+    categories: &[Category],
+    mut rng: &mut impl Rng,
+) -> Vec<OrderedStudent> {
+    let indices = category_indices(categories);
+
+    // Members of a group enter the lottery once.
+    let mut units: Vec<Vec<(usize, &Student)>> = Vec::with_capacity(students.len());
+    let mut group_units: HashMap<String, usize> = HashMap::new();
+    for (id, student) in students.iter().enumerate() {
+        let unit = match &student.group {
+            Some(group) => *group_units.entry(group.name.clone()).or_insert_with(|| {
+                units.push(Vec::new());
+                units.len() - 1
+            }),
+            None => {
+                units.push(Vec::new());
+                units.len() - 1
+            }
+        };
+        units[unit].push((id, student));
+    }
 
-    students
-        .into_iter()
-        .enumerate()
-        .map(|(i, s)| OrderedStudent {
-            name: s.name,
-            preferences: s.preferences,
-            exclude: s.exclude,
-            order: i,
-        })
-        .collect()
+    units.shuffle(&mut rng);
+
+    let mut ordered_students = Vec::with_capacity(units.len());
+    for (i, unit) in units.into_iter().enumerate() {
+        let mut members: Vec<OrderedStudent> = unit
+            .into_iter()
+            .map(|(id, s)| OrderedStudent::new(s, id, i, &indices))
+            .collect();
+
+        // Members break ties the same way, so they keep applying together.
+        members[0].break_ties(&mut rng);
+        let preferences = members[0].preferences.clone();
+        for member in members.iter_mut().skip(1) {
+            member.preferences = preferences.clone();
+        }
+
+        ordered_students.extend(members);
+    }
+    ordered_students
 }
 
-fn place_students(
+pub(crate) fn place_students(
     unplaced_students: Vec<OrderedStudent>,
-    placed: &mut HashMap<String, Vec<OrderedStudent>>,
+    placed: &mut StudentsPerCategory,
     not_placable: &mut Vec<OrderedStudent>,
 ) {
     for mut student in unplaced_students.into_iter() {
-        if let Some(category) = student.preferences.pop_front() {
-            if student.exclude.contains(&category) {
-                not_placable.push(student);
-            } else {
-                match placed.get_mut(&category.name) {
-                    Some(placed_students) => placed_students.push(student),
-                    None => {
-                        placed.insert(category.name, vec![student]);
-                    }
-                }
-            }
-        } else {
-            not_placable.push(student);
+        match student.next_preference() {
+            Some(category) if !student.excludes(category) => placed[category].push(student),
+            _ => not_placable.push(student),
         }
     }
 }
 
-fn truncate_categories(
-    placed: &mut HashMap<String, Vec<OrderedStudent>>,
-    categories: &Vec<Category>,
+pub(crate) fn truncate_categories(
+    placed: &mut StudentsPerCategory,
+    categories: &[Category],
+    priorities: &Priorities,
+    capacities: &[usize],
+    waitlists: &mut StudentsPerCategory,
 ) -> Vec<OrderedStudent> {
     let mut unplaced_students: Vec<OrderedStudent> = Vec::new();
 
-    for category in categories {
-        if let Some(placed_students) = placed.get_mut(&category.name) {
-            if placed_students.len() > category.max_placements {
-                placed_students.sort();
-                for student in placed_students.drain(category.max_placements..placed_students.len())
-                {
-                    unplaced_students.push(student);
-                }
-            }
+    for (i, category) in categories.iter().enumerate() {
+        let placed_students = &mut placed[i];
+        if placed_students.len() > capacities[i] {
+            sort_by_priority(placed_students, priorities, i);
+            let (accepted, rejected) =
+                select_students(std::mem::take(placed_students), category, capacities[i]);
+
+            waitlists[i].extend(rejected.iter().cloned());
+            unplaced_students.extend(rejected);
+            *placed_students = accepted;
         }
     }
     unplaced_students
 }
 
-fn assign_random(
-    mut not_placable: Vec<OrderedStudent>,
-    placed: &mut HashMap<String, Vec<OrderedStudent>>,
-    categories: &Vec<Category>,
+/// Select the students that get one of the seats of the category from students sorted by
+/// priority, and return the accepted and rejected students in that order
+///
+/// Groups and single students are accepted in order as long as they fit. Reserved seats
+/// only go to students with the attribute of the reserve and are filled before or after the
+/// open seats, as set by the category. Reserved seats that are left are open to everyone.
+pub(crate) fn select_students(
+    students: Vec<OrderedStudent>,
+    category: &Category,
+    seats: usize,
+) -> (Vec<OrderedStudent>, Vec<OrderedStudent>) {
+    let units = split_units(students);
+    let mut selected = vec![false; units.len()];
+    let mut seats_left = seats;
+
+    // Take units that qualify in order, as long as they fit in the seats, and return the
+    // number of seats taken.
+    let mut fill = |qualifies: &dyn Fn(&OrderedStudent) -> bool, seats: usize| {
+        let mut taken = 0;
+        for (unit, selected) in units.iter().zip(selected.iter_mut()) {
+            if !*selected && unit.iter().all(qualifies) && taken + unit.len() <= seats {
+                *selected = true;
+                taken += unit.len();
+            }
+        }
+        taken
+    };
+
+    let reserved_seats: usize = category.reserves.iter().map(|r| r.seats).sum();
+    if category.reserve_order == ReserveOrder::OpenFirst {
+        seats_left -= fill(&|_| true, seats_left.saturating_sub(reserved_seats));
+    }
+    for reserve in category.reserves.iter() {
+        seats_left -= fill(
+            &|s| s.attributes.contains(&reserve.attribute),
+            reserve.seats.min(seats_left),
+        );
+    }
+    fill(&|_| true, seats_left);
+
+    let mut accepted: Vec<OrderedStudent> = Vec::with_capacity(seats);
+    let mut rejected: Vec<OrderedStudent> = Vec::new();
+    for (unit, selected) in units.into_iter().zip(selected) {
+        if selected {
+            accepted.extend(unit);
+        } else {
+            rejected.extend(unit);
+        }
+    }
+    (accepted, rejected)
+}
+
+/// Split students in groups and single students, keeping their order
+fn split_units(students: Vec<OrderedStudent>) -> Vec<Vec<OrderedStudent>> {
+    let mut units: Vec<Vec<OrderedStudent>> = Vec::new();
+    for student in students.into_iter() {
+        match units.last_mut() {
+            Some(unit) if unit[0].unit() == student.unit() => unit.push(student),
+            _ => units.push(vec![student]),
+        }
+    }
+    units
+}
+
+/// Sort students so the students with the highest priority for the category come first,
+/// using the lottery to break ties
+///
+/// Members of a group share the highest priority of its members. Students that are in order
+/// already, like the students a category accepted in an earlier round, are only merged with
+/// the students that follow them.
+pub(crate) fn sort_by_priority(
+    students: &mut Vec<OrderedStudent>,
+    priorities: &Priorities,
+    category: usize,
+) {
+    let compare = |a: &OrderedStudent, b: &OrderedStudent| {
+        (Reverse(priorities.get(category, a)), a.order, a.unit()).cmp(&(
+            Reverse(priorities.get(category, b)),
+            b.order,
+            b.unit(),
+        ))
+    };
+
+    let sorted = students
+        .windows(2)
+        .take_while(|w| compare(&w[0], &w[1]) != Ordering::Greater)
+        .count()
+        + 1;
+    if sorted >= students.len() {
+        return;
+    }
+
+    let mut others = students.split_off(sorted);
+    others.sort_by(compare);
+
+    let mut merged: Vec<OrderedStudent> = Vec::with_capacity(students.len() + others.len());
+    let mut in_order = std::mem::take(students).into_iter().peekable();
+    let mut others = others.into_iter().peekable();
+    while let (Some(a), Some(b)) = (in_order.peek(), others.peek()) {
+        if compare(b, a) == Ordering::Less {
+            merged.extend(others.next());
+        } else {
+            merged.extend(in_order.next());
+        }
+    }
+    merged.extend(in_order);
+    merged.extend(others);
+    *students = merged;
+}
+
+/// Place students that could not be placed in their preferences by the fallback policy
+pub(crate) fn assign_fallback(
+    not_placable: Vec<OrderedStudent>,
+    placed: &mut StudentsPerCategory,
+    categories: &[Category],
+    fallback: &Fallback,
+    rng: &mut impl Rng,
+) -> Vec<OrderedStudent> {
+    match fallback {
+        Fallback::None => not_placable,
+        Fallback::Random => assign_random(not_placable, placed, categories, rng),
+        Fallback::LowestCost(costs) => {
+            assign_lowest_cost(not_placable, placed, categories, |s, c| {
+                costs.get(s).and_then(|c_costs| c_costs.get(c)).copied()
+            })
+        }
+        Fallback::SecondRound(preferences) => {
+            assign_lowest_cost(not_placable, placed, categories, |s, c| {
+                preferences
+                    .get(s)
+                    .and_then(|p| p.iter().position(|name| name == c))
+                    .map(|position| position as u32)
+            })
+        }
+    }
+}
+
+pub(crate) fn assign_random(
+    not_placable: Vec<OrderedStudent>,
+    placed: &mut StudentsPerCategory,
+    categories: &[Category],
     mut rng: &mut impl Rng,
+) -> Vec<OrderedStudent> {
+    assign_units(not_placable, placed, categories, |_, open_categories| {
+        open_categories.iter().choose(&mut rng).copied()
+    })
+}
+
+/// Place every unit in the open category with the lowest total cost of its members, where
+/// categories without a cost for every member are not an option
+fn assign_lowest_cost(
+    not_placable: Vec<OrderedStudent>,
+    placed: &mut StudentsPerCategory,
+    categories: &[Category],
+    cost: impl Fn(&str, &str) -> Option<u32>,
+) -> Vec<OrderedStudent> {
+    assign_units(not_placable, placed, categories, |unit, open_categories| {
+        open_categories
+            .iter()
+            .filter_map(|&c| {
+                let costs: Option<Vec<u32>> = unit
+                    .iter()
+                    .map(|s| cost(&s.name, &categories[c].name))
+                    .collect();
+                costs.map(|costs| (costs.iter().map(|&cost| u64::from(cost)).sum::<u64>(), c))
+            })
+            // Break ties in favour of the first category.
+            .min_by_key(|&(total, _)| total)
+            .map(|(_, c)| c)
+    })
+}
+
+/// Place students in order of their lot in the open category picked by `choose`, which gets
+/// the indices of the open categories
+fn assign_units(
+    mut not_placable: Vec<OrderedStudent>,
+    placed: &mut StudentsPerCategory,
+    categories: &[Category],
+    mut choose: impl FnMut(&[OrderedStudent], &[usize]) -> Option<usize>,
 ) -> Vec<OrderedStudent> {
     // Sort in order so best lots gets selected first.
-    not_placable.sort();
+    not_placable.sort_by(|a, b| (a.order, a.unit()).cmp(&(b.order, b.unit())));
 
     let mut still_not_placable: Vec<OrderedStudent> = Vec::new();
 
-    for student in not_placable.into_iter() {
-        let open_categories: Vec<&Category> = categories
-            .iter()
-            .filter(|c| {
-                placed
-                    .get(&c.name)
-                    .unwrap_or(&Vec::<OrderedStudent>::new())
-                    .len()
-                    < c.max_placements
-            })
-            .filter(|c| !student.exclude.contains(&c))
+    // Members of a group are assigned to the same category.
+    for mut unit in split_units(not_placable) {
+        let open_categories: Vec<usize> = (0..categories.len())
+            .filter(|&c| placed[c].len() + unit.len() <= categories[c].max_placements)
+            .filter(|&c| !unit.iter().any(|s| s.excludes(c)))
             .collect();
 
-        if let Some(&category) = open_categories.iter().choose(&mut rng) {
-            placed
-                .entry(category.name.clone())
-                .or_insert(Vec::<OrderedStudent>::new())
-                .push(student);
+        if let Some(category) = choose(&unit, &open_categories) {
+            for student in unit.iter_mut() {
+                student.rank = None;
+            }
+            placed[category].extend(unit);
         } else {
-            still_not_placable.push(student);
+            still_not_placable.extend(unit);
         }
     }
 
@@ -338,51 +1553,55 @@ fn assign_random(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Group;
     use rand::rngs::mock::StepRng;
-    use std::collections::VecDeque;
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
     #[test]
     fn test_draw_order() {
         let mut rng = StepRng::new(2, 0);
 
         let students = vec![
-            Student {
-                name: "Bert".into(),
-                preferences: VecDeque::new(),
-                exclude: Vec::new(),
-            },
-            Student {
-                name: "Kate".into(),
-                preferences: VecDeque::new(),
-                exclude: Vec::new(),
-            },
-            Student {
-                name: "Harry".into(),
-                preferences: VecDeque::new(),
-                exclude: Vec::new(),
-            },
+            Student::new("Bert", VecDeque::new(), Vec::new()),
+            Student::new("Kate", VecDeque::new(), Vec::new()),
+            Student::new("Harry", VecDeque::new(), Vec::new()),
         ];
 
-        let ordered_students = draw_order(students, &mut rng);
+        let ordered_students = draw_order(&students, &[], &mut rng);
 
         let assert_ordered_students = vec![
             OrderedStudent {
                 name: "Kate".into(),
+                id: 1,
                 preferences: VecDeque::new(),
                 exclude: Vec::new(),
                 order: 0,
+                rank: None,
+                preference_ranks: VecDeque::new(),
+                group: None,
+                attributes: BTreeSet::new(),
             },
             OrderedStudent {
                 name: "Harry".into(),
+                id: 2,
                 preferences: VecDeque::new(),
                 exclude: Vec::new(),
                 order: 1,
+                rank: None,
+                preference_ranks: VecDeque::new(),
+                group: None,
+                attributes: BTreeSet::new(),
             },
             OrderedStudent {
                 name: "Bert".into(),
+                id: 0,
                 preferences: VecDeque::new(),
                 exclude: Vec::new(),
                 order: 2,
+                rank: None,
+                preference_ranks: VecDeque::new(),
+                group: None,
+                attributes: BTreeSet::new(),
             },
         ];
 
@@ -391,213 +1610,311 @@ mod tests {
 
     #[test]
     fn test_place_students() {
-        let cooking = Category {
-            name: "Cooking".into(),
-            max_placements: 3,
-        };
-        let reading = Category {
-            name: "Reading".into(),
-            max_placements: 2,
-        };
-        let walking = Category {
-            name: "Walking".into(),
-            max_placements: 1,
-        };
+        // Categories cooking, reading and walking by their index
 
         let mut bert = OrderedStudent {
             name: "Bert".into(),
-            preferences: VecDeque::from(vec![cooking.clone(), reading.clone(), walking.clone()]),
+            id: 0,
+            preferences: VecDeque::from(vec![0, 1, 2]),
             exclude: Vec::new(),
             order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let mut kate = OrderedStudent {
             name: "Kate".into(),
-            preferences: VecDeque::from(vec![walking.clone()]),
+            id: 1,
+            preferences: VecDeque::from(vec![2]),
             exclude: Vec::new(),
             order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let mut suze = OrderedStudent {
             name: "Suze".into(),
-            preferences: VecDeque::from(vec![walking.clone(), cooking.clone()]),
+            id: 2,
+            preferences: VecDeque::from(vec![2, 0]),
             exclude: Vec::new(),
             order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let harry = OrderedStudent {
             name: "Harry".into(),
+            id: 3,
             preferences: VecDeque::new(),
             exclude: Vec::new(),
             order: 3,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
 
         let unplaced_students = vec![bert.clone(), kate.clone(), suze.clone(), harry.clone()];
 
-        let mut placed: HashMap<String, Vec<OrderedStudent>> = HashMap::new();
+        let mut placed: StudentsPerCategory = vec![Vec::new(); 3];
         let mut not_placable: Vec<OrderedStudent> = Vec::new();
 
         place_students(unplaced_students, &mut placed, &mut not_placable);
 
-        let mut assert_placed = HashMap::new();
         bert.preferences.remove(0);
+        bert.rank = Some(1);
         kate.preferences.remove(0);
+        kate.rank = Some(1);
         suze.preferences.remove(0);
-        assert_placed.insert(cooking.name, vec![bert]);
-        assert_placed.insert(walking.name, vec![kate, suze]);
+        suze.rank = Some(1);
 
-        assert_eq!(placed, assert_placed);
+        assert_eq!(placed, vec![vec![bert], Vec::new(), vec![kate, suze]]);
         assert_eq!(not_placable, vec![harry]);
     }
 
     #[test]
     fn test_place_students_with_exclude() {
-        let cooking = Category {
-            name: "Cooking".into(),
-            max_placements: 3,
-        };
-        let reading = Category {
-            name: "Reading".into(),
-            max_placements: 2,
-        };
+        // Categories cooking and reading by their index
 
         let mut bert = OrderedStudent {
             name: "Bert".into(),
-            preferences: VecDeque::from(vec![cooking.clone(), reading.clone()]),
+            id: 0,
+            preferences: VecDeque::from(vec![0, 1]),
             exclude: Vec::new(),
             order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let mut kate = OrderedStudent {
             name: "Kate".into(),
-            preferences: VecDeque::from(vec![cooking.clone()]),
-            exclude: Vec::from(vec![cooking.clone(), reading.clone()]),
+            id: 1,
+            preferences: VecDeque::from(vec![0]),
+            exclude: vec![0, 1],
             order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
 
         let unplaced_students = vec![bert.clone(), kate.clone()];
 
-        let mut placed: HashMap<String, Vec<OrderedStudent>> = HashMap::new();
+        let mut placed: StudentsPerCategory = vec![Vec::new(); 2];
         let mut not_placable: Vec<OrderedStudent> = Vec::new();
 
         place_students(unplaced_students, &mut placed, &mut not_placable);
 
-        let mut assert_placed = HashMap::new();
         bert.preferences.remove(0);
+        bert.rank = Some(1);
         kate.preferences.remove(0);
-        assert_placed.insert(cooking.name, vec![bert]);
+        kate.rank = Some(1);
 
-        assert_eq!(placed, assert_placed);
+        assert_eq!(placed, vec![vec![bert], Vec::new()]);
         assert_eq!(not_placable, vec![kate]);
     }
 
     #[test]
     fn test_truncate_categories() {
-        let cooking = Category {
-            name: "Cooking".into(),
-            max_placements: 3,
-        };
-        let reading = Category {
-            name: "Reading".into(),
-            max_placements: 2,
-        };
-        let walking = Category {
-            name: "Walking".into(),
-            max_placements: 1,
-        };
+        let cooking = Category::new("Cooking", 3);
+        let reading = Category::new("Reading", 2);
+        let walking = Category::new("Walking", 1);
 
         let bert = OrderedStudent {
             name: "Bert".into(),
-            preferences: VecDeque::from(vec![reading.clone(), walking.clone()]),
+            id: 0,
+            preferences: VecDeque::from(vec![1, 2]),
             exclude: Vec::new(),
             order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let kate = OrderedStudent {
             name: "Kate".into(),
+            id: 1,
             preferences: VecDeque::new(),
             exclude: Vec::new(),
             order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let suze = OrderedStudent {
             name: "Suze".into(),
-            preferences: VecDeque::from(vec![cooking.clone()]),
+            id: 2,
+            preferences: VecDeque::from(vec![0]),
             exclude: Vec::new(),
             order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let harry = OrderedStudent {
             name: "Harry".into(),
-            preferences: VecDeque::from(vec![walking.clone()]),
+            id: 3,
+            preferences: VecDeque::from(vec![2]),
             exclude: Vec::new(),
             order: 3,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
 
-        let mut placed = HashMap::new();
-        placed.insert(cooking.name.clone(), vec![bert]);
-        placed.insert(
-            walking.name.clone(),
+        let mut placed = vec![
+            vec![bert],
+            Vec::new(),
             vec![kate, suze.clone(), harry.clone()],
-        );
+        ];
 
         let mut assert_placed = placed.clone();
-        assert_placed.get_mut(&walking.name).unwrap().pop();
-        assert_placed.get_mut(&walking.name).unwrap().pop();
+        assert_placed[2].pop();
+        assert_placed[2].pop();
 
-        let categories: Vec<Category> = vec![cooking.clone(), reading.clone(), walking.clone()];
+        let categories: Vec<Category> = vec![cooking, reading, walking];
+        let capacities: Vec<usize> = categories.iter().map(|c| c.max_placements).collect();
 
-        let unplaced_students = truncate_categories(&mut placed, &categories);
+        let priorities = Priorities::new(&[], &categories);
+
+        let mut waitlists = vec![Vec::new(); 3];
+        let unplaced_students = truncate_categories(
+            &mut placed,
+            &categories,
+            &priorities,
+            &capacities,
+            &mut waitlists,
+        );
 
         assert_eq!(placed, assert_placed);
         assert_eq!(unplaced_students, vec![suze, harry]);
     }
 
     #[test]
-    fn test_assign_random() {
-        let mut rng = StepRng::new(2, 0);
+    fn test_truncate_categories_with_priorities() {
+        // Harry has the worst lot, but his priority places him before Kate and Suze.
+        let walking = Category::new("Walking", 2).with_priority("Harry", 1);
 
-        let cooking = Category {
-            name: "Cooking".into(),
-            max_placements: 3,
+        let kate = OrderedStudent {
+            name: "Kate".into(),
+            id: 0,
+            preferences: VecDeque::new(),
+            exclude: Vec::new(),
+            order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
-        let reading = Category {
-            name: "Reading".into(),
-            max_placements: 2,
+        let suze = OrderedStudent {
+            name: "Suze".into(),
+            id: 1,
+            preferences: VecDeque::new(),
+            exclude: Vec::new(),
+            order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
-        let walking = Category {
-            name: "Walking".into(),
-            max_placements: 1,
+        let harry = OrderedStudent {
+            name: "Harry".into(),
+            id: 2,
+            preferences: VecDeque::new(),
+            exclude: Vec::new(),
+            order: 3,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
 
+        let mut placed = vec![vec![kate.clone(), suze.clone(), harry.clone()]];
+
+        let categories: Vec<Category> = vec![walking];
+        let students: Vec<Student> = ["Kate", "Suze", "Harry"]
+            .iter()
+            .map(|name| Student::new(name, VecDeque::new(), Vec::new()))
+            .collect();
+        let priorities = Priorities::new(&students, &categories);
+
+        let mut waitlists = vec![Vec::new()];
+        let unplaced_students =
+            truncate_categories(&mut placed, &categories, &priorities, &[2], &mut waitlists);
+
+        assert_eq!(placed[0], vec![harry, kate]);
+        assert_eq!(unplaced_students, vec![suze.clone()]);
+        assert_eq!(waitlists[0], vec![suze]);
+    }
+
+    #[test]
+    fn test_assign_random() {
+        let mut rng = StepRng::new(2, 0);
+
+        let cooking = Category::new("Cooking", 3);
+        let reading = Category::new("Reading", 2);
+        let walking = Category::new("Walking", 1);
+
         let bert = OrderedStudent {
             name: "Bert".into(),
-            preferences: VecDeque::from(vec![cooking.clone(), reading.clone(), walking.clone()]),
+            id: 0,
+            preferences: VecDeque::from(vec![0, 1, 2]),
             exclude: Vec::new(),
             order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let kate = OrderedStudent {
             name: "Kate".into(),
-            preferences: VecDeque::from(vec![walking.clone()]),
+            id: 1,
+            preferences: VecDeque::from(vec![2]),
             exclude: Vec::new(),
             order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let suze = OrderedStudent {
             name: "Suze".into(),
-            preferences: VecDeque::from(vec![walking.clone(), cooking.clone()]),
+            id: 2,
+            preferences: VecDeque::from(vec![2, 0]),
             exclude: Vec::new(),
             order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let harry = OrderedStudent {
             name: "Harry".into(),
+            id: 3,
             preferences: VecDeque::new(),
             exclude: Vec::new(),
             order: 3,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
 
         let not_placable: Vec<OrderedStudent> = vec![harry.clone()];
 
-        let mut placed = HashMap::new();
-        placed.insert(cooking.name.clone(), vec![bert]);
-        placed.insert(walking.name.clone(), vec![kate, suze]);
+        let mut placed = vec![vec![bert], Vec::new(), vec![kate, suze]];
 
         let mut assert_placed = placed.clone();
-        assert_placed.get_mut(&cooking.name).unwrap().push(harry);
+        assert_placed[0].push(harry);
 
-        let categories: Vec<Category> = vec![cooking.clone(), reading.clone(), walking.clone()];
+        let categories: Vec<Category> = vec![cooking, reading, walking];
 
         let not_placable = assign_random(not_placable, &mut placed, &categories, &mut rng);
 
@@ -609,61 +1926,74 @@ mod tests {
     fn assign_random_full() {
         let mut rng = StepRng::new(2, 0);
 
-        let cooking = Category {
-            name: "Cooking".into(),
-            max_placements: 1,
-        };
-        let reading = Category {
-            name: "Reading".into(),
-            max_placements: 1,
-        };
-        let walking = Category {
-            name: "Walking".into(),
-            max_placements: 2,
-        };
+        let cooking = Category::new("Cooking", 1);
+        let reading = Category::new("Reading", 1);
+        let walking = Category::new("Walking", 2);
 
         let bert = OrderedStudent {
             name: "Bert".into(),
-            preferences: VecDeque::from(vec![cooking.clone(), reading.clone(), walking.clone()]),
+            id: 0,
+            preferences: VecDeque::from(vec![0, 1, 2]),
             exclude: Vec::new(),
             order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let kate = OrderedStudent {
             name: "Kate".into(),
-            preferences: VecDeque::from(vec![walking.clone()]),
+            id: 1,
+            preferences: VecDeque::from(vec![2]),
             exclude: Vec::new(),
             order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let suze = OrderedStudent {
             name: "Suze".into(),
-            preferences: VecDeque::from(vec![walking.clone(), cooking.clone()]),
+            id: 2,
+            preferences: VecDeque::from(vec![2, 0]),
             exclude: Vec::new(),
             order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let harry = OrderedStudent {
             name: "Harry".into(),
+            id: 3,
             preferences: VecDeque::new(),
             exclude: Vec::new(),
             order: 3,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let lisa = OrderedStudent {
             name: "Lisa".into(),
+            id: 4,
             preferences: VecDeque::new(),
             exclude: Vec::new(),
             order: 4,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
 
         let not_placable: Vec<OrderedStudent> = vec![harry.clone(), lisa.clone()];
 
-        let mut placed = HashMap::new();
-        placed.insert(cooking.name.clone(), vec![bert]);
-        placed.insert(walking.name.clone(), vec![kate]);
-        placed.insert(reading.name.clone(), vec![suze]);
+        let mut placed = vec![vec![bert], vec![suze], vec![kate]];
 
         let mut assert_placed = placed.clone();
-        assert_placed.get_mut(&walking.name).unwrap().push(harry);
+        assert_placed[2].push(harry);
 
-        let categories: Vec<Category> = vec![cooking.clone(), reading.clone(), walking.clone()];
+        let categories: Vec<Category> = vec![cooking, reading, walking];
 
         let not_placable = assign_random(not_placable, &mut placed, &categories, &mut rng);
 
@@ -676,46 +2006,278 @@ mod tests {
     fn test_assign_random_exclude() {
         let mut rng = StepRng::new(2, 0);
 
-        let cooking = Category {
-            name: "Cooking".into(),
-            max_placements: 1,
-        };
-        let reading = Category {
-            name: "Reading".into(),
-            max_placements: 2,
-        };
+        let cooking = Category::new("Cooking", 1);
+        let reading = Category::new("Reading", 2);
 
         let bert = OrderedStudent {
             name: "Bert".into(),
-            preferences: VecDeque::from(vec![cooking.clone(), reading.clone()]),
+            id: 0,
+            preferences: VecDeque::from(vec![0, 1]),
             exclude: Vec::new(),
             order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let kate = OrderedStudent {
             name: "Kate".into(),
+            id: 1,
             preferences: VecDeque::new(),
-            exclude: Vec::from(vec![reading.clone()]),
+            exclude: vec![1],
             order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
         let ludo = OrderedStudent {
             name: "Ludo".into(),
+            id: 2,
             preferences: VecDeque::new(),
-            exclude: Vec::from(vec![reading.clone()]),
+            exclude: vec![1],
             order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
         };
 
         let not_placable: Vec<OrderedStudent> = vec![kate.clone(), ludo.clone()];
 
-        let mut placed = HashMap::new();
-        placed.insert(cooking.name.clone(), vec![bert]);
+        let mut placed = vec![vec![bert], Vec::new()];
 
         let assert_placed = placed.clone();
 
-        let categories: Vec<Category> = vec![cooking.clone(), reading.clone()];
+        let categories: Vec<Category> = vec![cooking, reading];
 
         let not_placable = assign_random(not_placable, &mut placed, &categories, &mut rng);
 
         assert_eq!(placed, assert_placed);
         assert_eq!(not_placable, vec![kate, ludo]);
     }
+
+    #[test]
+    fn test_assign_fallback() {
+        let mut rng = StepRng::new(2, 0);
+
+        let cooking = Category::new("Cooking", 1);
+        let reading = Category::new("Reading", 1);
+        let walking = Category::new("Walking", 1);
+
+        let bert = OrderedStudent {
+            name: "Bert".into(),
+            id: 0,
+            preferences: VecDeque::new(),
+            exclude: Vec::new(),
+            order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let harry = OrderedStudent {
+            name: "Harry".into(),
+            id: 1,
+            preferences: VecDeque::new(),
+            exclude: vec![2],
+            order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let lisa = OrderedStudent {
+            name: "Lisa".into(),
+            id: 2,
+            preferences: VecDeque::new(),
+            exclude: Vec::new(),
+            order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+
+        let mut placed = vec![vec![bert.clone()], Vec::new(), Vec::new()];
+        let categories: Vec<Category> = vec![cooking, reading, walking];
+
+        let not_placable = assign_fallback(
+            vec![harry.clone(), lisa.clone()],
+            &mut placed.clone(),
+            &categories,
+            &Fallback::None,
+            &mut rng,
+        );
+        assert_eq!(not_placable, vec![harry.clone(), lisa.clone()]);
+
+        // Cooking is full and Harry excludes walking, Lisa has no costs.
+        let mut costs = BTreeMap::new();
+        costs.insert(
+            "Harry".to_string(),
+            vec![("Cooking", 1), ("Walking", 2), ("Reading", 3)]
+                .into_iter()
+                .map(|(c, cost)| (c.to_string(), cost))
+                .collect(),
+        );
+        let mut cost_placed = placed.clone();
+        let not_placable = assign_fallback(
+            vec![harry.clone(), lisa.clone()],
+            &mut cost_placed,
+            &categories,
+            &Fallback::LowestCost(costs),
+            &mut rng,
+        );
+        assert_eq!(not_placable, vec![lisa.clone()]);
+        assert_eq!(cost_placed[1], vec![harry.clone()]);
+
+        // Harry gets the first open category of the second round, before Lisa.
+        let mut preferences = BTreeMap::new();
+        preferences.insert("Harry".to_string(), vec!["Reading".to_string()]);
+        preferences.insert(
+            "Lisa".to_string(),
+            vec!["Reading".to_string(), "Walking".to_string()],
+        );
+        let not_placable = assign_fallback(
+            vec![lisa.clone(), harry.clone()],
+            &mut placed,
+            &categories,
+            &Fallback::SecondRound(preferences),
+            &mut rng,
+        );
+        assert!(not_placable.is_empty());
+        assert_eq!(placed[1], vec![harry]);
+        assert_eq!(placed[2], vec![lisa]);
+    }
+
+    #[test]
+    fn test_draw_order_breaks_ties() {
+        let mut rng = StepRng::new(2, 0);
+
+        let cooking = Category::new("Cooking", 1);
+        let reading = Category::new("Reading", 1);
+        let walking = Category::new("Walking", 1);
+
+        let bert = Student::with_indifference_groups(
+            "Bert",
+            vec![
+                vec![cooking.clone(), reading.clone()],
+                vec![walking.clone()],
+            ],
+            Vec::new(),
+        );
+
+        let categories = vec![cooking, reading, walking];
+        let mut ordered_students = draw_order(&[bert], &categories, &mut rng);
+        let mut student = ordered_students.pop().unwrap();
+
+        assert_eq!(student.preference_ranks, VecDeque::from(vec![1, 1, 2]));
+        assert_eq!(student.preferences[2], 2);
+
+        let first = student.next_preference().unwrap();
+        assert!(first == 0 || first == 1);
+        assert_eq!(student.rank, Some(1));
+        student.next_preference();
+        assert_eq!(student.rank, Some(1));
+        assert_eq!(student.next_preference(), Some(2));
+        assert_eq!(student.rank, Some(2));
+    }
+
+    #[test]
+    fn test_cancel_category() {
+        let cooking = Category::new("Cooking", 2).with_min_placements(2);
+        let reading = Category::new("Reading", 1);
+        let walking = Category::new("Walking", 1);
+
+        let mut students = vec![Student::with_indifference_groups(
+            "Bert",
+            vec![
+                vec![cooking.clone(), reading.clone()],
+                vec![walking.clone()],
+            ],
+            Vec::new(),
+        )];
+        let mut categories = vec![cooking, reading.clone(), walking.clone()];
+
+        cancel_category(&mut students, &mut categories, "Cooking");
+
+        assert_eq!(categories[0].max_placements, 0);
+        assert_eq!(
+            students[0].preferences,
+            VecDeque::from(vec![reading, walking])
+        );
+        assert!(students[0].indifference_groups.is_empty());
+    }
+
+    #[test]
+    fn test_select_students_with_reserve() {
+        let category = Category::new("Cooking", 2).with_reserve("low-income", 1);
+
+        // Suze has the best lot, then Bert, then Kate
+        let students: Vec<OrderedStudent> = vec![
+            Student::new("Suze", VecDeque::new(), Vec::new()).with_attribute("low-income"),
+            Student::new("Bert", VecDeque::new(), Vec::new()),
+            Student::new("Kate", VecDeque::new(), Vec::new()).with_attribute("low-income"),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, s)| OrderedStudent::new(&s, i, i, &HashMap::new()))
+        .collect();
+        let names = |students: Vec<OrderedStudent>| -> Vec<String> {
+            students.into_iter().map(|s| s.name).collect()
+        };
+
+        let (accepted, rejected) = select_students(students.clone(), &category, 2);
+        assert_eq!(names(accepted), vec!["Suze", "Bert"]);
+        assert_eq!(names(rejected), vec!["Kate"]);
+
+        // Suze takes an open seat, leaving the reserved seat for Kate
+        let category = category.with_reserve_order(ReserveOrder::OpenFirst);
+        let (accepted, rejected) = select_students(students.clone(), &category, 2);
+        assert_eq!(names(accepted), vec!["Suze", "Kate"]);
+        assert_eq!(names(rejected), vec!["Bert"]);
+
+        // Reserved seats nobody qualifies for are open to everyone
+        let category = Category::new("Cooking", 2).with_reserve("district-north", 1);
+        let (accepted, _) = select_students(students, &category, 2);
+        assert_eq!(names(accepted), vec!["Suze", "Bert"]);
+    }
+
+    #[test]
+    fn test_sort_by_priority() {
+        let category = Category::new("Cooking", 2).with_priority("Harry", 1);
+
+        // Kate and Harry are a group with the worst lot, which shares Harry's priority
+        let students = vec![
+            Student::new("Suze", VecDeque::new(), Vec::new()),
+            Student::new("Bert", VecDeque::new(), Vec::new()),
+            Student::new("Kate", VecDeque::new(), Vec::new()).with_group(Group::hard("Kids")),
+            Student::new("Harry", VecDeque::new(), Vec::new()).with_group(Group::hard("Kids")),
+            Student::new("Lisa", VecDeque::new(), Vec::new()),
+        ];
+        let priorities = Priorities::new(&students, &[category]);
+        let orders = [0, 1, 3, 3, 2];
+        let ordered: Vec<OrderedStudent> = students
+            .iter()
+            .enumerate()
+            .map(|(i, s)| OrderedStudent::new(s, i, orders[i], &HashMap::new()))
+            .collect();
+        let names = |students: &[OrderedStudent]| -> Vec<String> {
+            students.iter().map(|s| s.name.clone()).collect()
+        };
+
+        // Suze and Bert are in order already, the others are merged with them
+        let mut sorted = vec![
+            ordered[0].clone(),
+            ordered[1].clone(),
+            ordered[4].clone(),
+            ordered[3].clone(),
+            ordered[2].clone(),
+        ];
+        sort_by_priority(&mut sorted, &priorities, 0);
+        assert_eq!(
+            names(&sorted),
+            vec!["Harry", "Kate", "Suze", "Bert", "Lisa"]
+        );
+    }
 }
@@ -0,0 +1,347 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Implements the Efficiency-Adjusted Deferred Acceptance algorithm (Kesten's EADAM)
+//!
+//! Deferred acceptance can place students worse than needed: a student that applies to a
+//! category, gets rejected from it later anyway and meanwhile pushed other students away,
+//! is an *interrupter*. Students that [consent](../struct.Student.html#structfield.consent)
+//! waive their priority in the categories they interrupted in, and deferred acceptance runs
+//! again with the same lottery. This repeats, starting with the interrupters of the last
+//! round, until no consenting student interrupts. Deferred acceptance runs once for every
+//! round with consenting interrupters, so matching takes considerably longer than `da_stb`
+//! when many students consent.
+//!
+//! Every student is placed at least as well as by [`da_stb`](../da_stb/index.html) with the
+//! same lottery, and a student that consents never loses its own placement by doing so. The
+//! categories in which students waived their priority are reported in
+//! [`MatchResult::waivers`](../struct.MatchResult.html#structfield.waivers).
+
+use super::da_stb::{
+    add_fixed, assign_fallback, draw_order, mark_fixed, place_students, sort_by_priority,
+    take_fixed, truncate_categories,
+};
+use super::validation::reject_groups;
+use super::{
+    validate, Category, Fallback, MatchError, MatchResult, Matcher, OrderedStudent, Priorities,
+    Student, StudentsPerCategory,
+};
+use rand::prelude::*;
+
+/// Efficiency-Adjusted Deferred Acceptance mechanism
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Eadam;
+
+impl Matcher for Eadam {
+    fn match_unchecked(
+        &self,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+        mut rng: &mut dyn RngCore,
+    ) -> MatchResult {
+        match_students_unchecked(students, categories, fallback, &mut rng)
+    }
+
+    fn validate(&self, students: &[Student], categories: &[Category]) -> Result<(), MatchError> {
+        validate(students, categories)?;
+        reject_groups(students)?;
+        Ok(())
+    }
+}
+
+/// Match students to categories
+///
+/// Use this function if each student can only be placed in one category
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::eadam::match_students;
+/// use matchmaker::{Category, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1)
+///     .with_priority("Bert", 2)
+///     .with_priority("Kate", 1);
+/// let reading = Category::new("Reading", 1).with_priority("Suze", 1);
+/// let walking = Category::new("Walking", 1);
+///
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![reading.clone(), cooking.clone()]),
+///     Vec::new(),
+/// );
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// // Kate pushes Suze out of cooking, but loses cooking to Bert anyway
+/// let kate = Student::new(
+///     "Kate",
+///     VecDeque::from(vec![cooking.clone(), walking.clone()]),
+///     Vec::new(),
+/// )
+/// .with_consent();
+///
+/// let mut rng = thread_rng();
+/// let categories = Vec::from([cooking, reading, walking]);
+///
+/// let match_result =
+///     match_students(Vec::from([bert, suze, kate]), &categories, &mut rng).unwrap();
+///
+/// // Kate waives her priority in cooking, so Bert and Suze both get their first choice
+/// assert_eq!(match_result.placed["Reading"][0], "Bert");
+/// assert_eq!(match_result.placed["Cooking"][0], "Suze");
+/// assert_eq!(match_result.placed["Walking"][0], "Kate");
+/// assert_eq!(match_result.waivers["Kate"], vec!["Cooking".to_string()]);
+/// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], or when students are part of a [`Group`], whose members can't waive
+/// their priority on their own.
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+/// [`Group`]: ../struct.Group.html
+pub fn match_students(
+    students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    Eadam.match_students(students, categories, rng)
+}
+
+pub(crate) fn match_students_unchecked(
+    students: &[Student],
+    categories: &[Category],
+    fallback: &Fallback,
+    mut rng: &mut impl Rng,
+) -> MatchResult {
+    let unplaced_students = draw_order(students, categories, &mut rng);
+    let priorities = Priorities::new(students, categories);
+    let (mut unplaced_students, fixed, capacities) = take_fixed(unplaced_students, categories);
+    let mut positions: Vec<usize> = vec![0; students.len()];
+    for (position, student) in unplaced_students.iter().enumerate() {
+        positions[student.id] = position;
+    }
+    let mut waivers: Vec<(usize, usize)> = Vec::new();
+
+    // Run deferred acceptance with the same lottery until no consenting student interrupts.
+    let (mut placed, not_placable, waitlists) = loop {
+        let (placed, not_placable, waitlists, interruptions) = defer_acceptance(
+            unplaced_students.clone(),
+            categories,
+            &priorities,
+            &capacities,
+        );
+
+        let consenting: Vec<Interruption> = interruptions
+            .into_iter()
+            .filter(|i| students[i.student].consent)
+            .collect();
+        let last_round = match consenting.iter().map(|i| i.round).max() {
+            Some(round) => round,
+            None => break (placed, not_placable, waitlists),
+        };
+
+        for interruption in consenting.iter().filter(|i| i.round == last_round) {
+            unplaced_students[positions[interruption.student]]
+                .remove_preference(interruption.category);
+            waivers.push((interruption.student, interruption.category));
+        }
+    };
+    add_fixed(&mut placed, fixed);
+
+    // Assign unplaced students to open spots by the fallback policy.
+    let not_placable = assign_fallback(not_placable, &mut placed, categories, fallback, &mut rng);
+
+    let mut match_result = MatchResult::from(placed, not_placable, waitlists, categories);
+    mark_fixed(&mut match_result, categories);
+    for (student, category) in waivers {
+        match_result
+            .waivers
+            .entry(students[student].name.clone())
+            .or_default()
+            .push(categories[category].name.clone());
+    }
+    match_result
+}
+
+/// A student that was rejected from a category after it made the category reject another
+/// student
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Interruption {
+    /// Round in which the student was rejected
+    round: usize,
+    /// Index of the student
+    student: usize,
+    /// Index of the category
+    category: usize,
+}
+
+/// Place students like [`da_stb`](../da_stb/index.html) and keep track of the students that
+/// interrupted
+///
+/// A student interrupts in a category when it is rejected from the category in a later round
+/// than it was accepted, and the category rejected another student in one of the rounds in
+/// between, starting with the round in which the student was accepted.
+fn defer_acceptance(
+    mut unplaced_students: Vec<OrderedStudent>,
+    categories: &[Category],
+    priorities: &Priorities,
+    capacities: &[usize],
+) -> (
+    StudentsPerCategory,
+    Vec<OrderedStudent>,
+    StudentsPerCategory,
+    Vec<Interruption>,
+) {
+    let student_count = unplaced_students
+        .iter()
+        .map(|s| s.id + 1)
+        .max()
+        .unwrap_or(0);
+    let mut not_placable: Vec<OrderedStudent> = vec![];
+    let mut placed: StudentsPerCategory = vec![Vec::new(); categories.len()];
+    let mut waitlists: StudentsPerCategory = vec![Vec::new(); categories.len()];
+    let mut interruptions: Vec<Interruption> = Vec::new();
+
+    // Round in which every student was accepted by its current category, and the last round
+    // in which every category rejected a student.
+    let mut accepted_in: Vec<usize> = vec![0; student_count];
+    let mut last_rejection: Vec<Option<usize>> = vec![None; categories.len()];
+    let mut held: Vec<bool> = vec![false; student_count];
+    let mut round = 0;
+
+    while !unplaced_students.is_empty() {
+        for student in unplaced_students.iter() {
+            accepted_in[student.id] = round;
+        }
+        place_students(unplaced_students, &mut placed, &mut not_placable);
+
+        // Only full categories reject students.
+        let applicants: Vec<Option<Vec<usize>>> = placed
+            .iter()
+            .zip(capacities)
+            .map(|(students, &capacity)| {
+                if students.len() > capacity {
+                    Some(students.iter().map(|s| s.id).collect())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        unplaced_students = truncate_categories(
+            &mut placed,
+            categories,
+            priorities,
+            capacities,
+            &mut waitlists,
+        );
+
+        for (category, applicants) in applicants.into_iter().enumerate() {
+            let applicants = match applicants {
+                Some(applicants) => applicants,
+                None => continue,
+            };
+            for student in placed[category].iter() {
+                held[student.id] = true;
+            }
+            let mut rejected = false;
+            for student in applicants {
+                if held[student] {
+                    held[student] = false;
+                    continue;
+                }
+                rejected = true;
+                if accepted_in[student] < round
+                    && last_rejection[category].is_some_and(|r| r >= accepted_in[student])
+                {
+                    interruptions.push(Interruption {
+                        round,
+                        student,
+                        category,
+                    });
+                }
+            }
+            if rejected {
+                last_rejection[category] = Some(round);
+            }
+        }
+
+        round += 1;
+    }
+
+    for (category, waitlist) in waitlists.iter_mut().enumerate() {
+        sort_by_priority(waitlist, priorities, category);
+    }
+
+    (placed, not_placable, waitlists, interruptions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeSet, VecDeque};
+
+    #[test]
+    fn test_defer_acceptance_finds_interruptions() {
+        // Cooking prefers Bert over Kate over Suze, reading prefers Suze over Bert
+        let categories = vec![
+            Category::new("Cooking", 1)
+                .with_priority("Bert", 2)
+                .with_priority("Kate", 1),
+            Category::new("Reading", 1).with_priority("Suze", 1),
+            Category::new("Walking", 1),
+        ];
+        let student = |name: &str, id: usize, preferences: Vec<usize>| OrderedStudent {
+            name: name.into(),
+            id,
+            preferences: VecDeque::from(preferences),
+            exclude: Vec::new(),
+            order: id,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+
+        // Kate pushes Suze out of cooking, and is pushed out herself by Bert two rounds later.
+        let students = vec![
+            student("Bert", 0, vec![1, 0, 2]),
+            student("Suze", 1, vec![0, 1, 2]),
+            student("Kate", 2, vec![0, 2]),
+        ];
+
+        let names: Vec<Student> = ["Bert", "Suze", "Kate"]
+            .iter()
+            .map(|name| Student::new(name, VecDeque::new(), Vec::new()))
+            .collect();
+        let priorities = Priorities::new(&names, &categories);
+
+        let (placed, not_placable, _, interruptions) =
+            defer_acceptance(students, &categories, &priorities, &[1, 1, 1]);
+
+        assert_eq!(placed[0][0].name, "Bert");
+        assert_eq!(placed[1][0].name, "Suze");
+        assert_eq!(placed[2][0].name, "Kate");
+        assert!(not_placable.is_empty());
+        assert_eq!(
+            interruptions,
+            vec![Interruption {
+                round: 2,
+                student: 2,
+                category: 0,
+            }]
+        );
+    }
+}
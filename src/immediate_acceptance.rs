@@ -0,0 +1,306 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Implements the Immediate Acceptance (or Boston) algorithm
+//!
+//! In every round the students that are not placed yet apply to their next preference.
+//! Categories accept applicants in order of their lot, as long as they have open spots,
+//! and these placements are final. Unlike deferred acceptance, a student placed in an earlier
+//! round can never be bumped by a student with a better lot in a later round.
+
+use super::da_stb::{assign_fallback, draw_order, place_students, sort_by_priority};
+use super::validation::{reject_fixed_placements, reject_groups, reject_reserves};
+use super::{
+    validate, Category, Fallback, MatchError, MatchResult, Matcher, OrderedStudent, Priorities,
+    Student, StudentsPerCategory,
+};
+use rand::prelude::*;
+
+/// Immediate Acceptance mechanism
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImmediateAcceptance;
+
+impl Matcher for ImmediateAcceptance {
+    fn match_unchecked(
+        &self,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+        mut rng: &mut dyn RngCore,
+    ) -> MatchResult {
+        match_students_unchecked(students, categories, fallback, &mut rng)
+    }
+
+    fn validate(&self, students: &[Student], categories: &[Category]) -> Result<(), MatchError> {
+        validate(students, categories)?;
+        reject_groups(students)?;
+        reject_reserves(categories)?;
+        reject_fixed_placements(categories)?;
+        Ok(())
+    }
+}
+
+/// Match students to categories
+///
+/// Use this function if each student can only be placed in one category
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::immediate_acceptance::match_students;
+/// use matchmaker::{Category, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// // Create categories
+/// let cooking = Category::new("Cooking", 1);
+/// let reading = Category::new("Reading", 1);
+///
+/// // Bert wishes to be placed in category cooking or reading (in that order)
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// // Suze wishes to be placed in category reading or cooking (in that order)
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![reading.clone(), cooking.clone()]),
+///     Vec::new(),
+/// );
+///
+/// let mut rng = thread_rng();
+/// let categories = Vec::from([cooking, reading]);
+///
+/// let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng).unwrap();
+///
+/// assert_eq!(match_result.placed.get("Cooking").unwrap()[0], "Bert");
+/// assert_eq!(match_result.placed.get("Reading").unwrap()[0], "Suze");
+/// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], or when students are part of a [`Group`] or categories have reserved
+/// seats or fixed placements, see [`Violation`].
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+/// [`Group`]: ../struct.Group.html
+/// [`Violation`]: ../enum.Violation.html
+pub fn match_students(
+    students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    ImmediateAcceptance.match_students(students, categories, rng)
+}
+
+pub(crate) fn match_students_unchecked(
+    students: &[Student],
+    categories: &[Category],
+    fallback: &Fallback,
+    mut rng: &mut impl Rng,
+) -> MatchResult {
+    let mut unplaced_students = draw_order(students, categories, &mut rng);
+    let priorities = Priorities::new(students, categories);
+    let mut not_placable: Vec<OrderedStudent> = vec![];
+    let mut placed: StudentsPerCategory = vec![Vec::new(); categories.len()];
+    let mut waitlists: StudentsPerCategory = vec![Vec::new(); categories.len()];
+
+    // Let students apply to their next preference, until everyone is placed or out of preferences
+    while !unplaced_students.is_empty() {
+        let mut applicants: StudentsPerCategory = vec![Vec::new(); categories.len()];
+        place_students(unplaced_students, &mut applicants, &mut not_placable);
+        unplaced_students = accept_applicants(
+            applicants,
+            &mut placed,
+            categories,
+            &priorities,
+            &mut waitlists,
+        );
+    }
+
+    // Assign unplaced students to open spots by the fallback policy.
+    let not_placable = assign_fallback(not_placable, &mut placed, categories, fallback, &mut rng);
+
+    for (category, waitlist) in waitlists.iter_mut().enumerate() {
+        sort_by_priority(waitlist, &priorities, category);
+    }
+
+    MatchResult::from(placed, not_placable, waitlists, categories)
+}
+
+/// Permanently place applicants while categories have open spots, and return the rejected ones
+fn accept_applicants(
+    applicants: StudentsPerCategory,
+    placed: &mut StudentsPerCategory,
+    categories: &[Category],
+    priorities: &Priorities,
+    waitlists: &mut StudentsPerCategory,
+) -> Vec<OrderedStudent> {
+    let mut rejected: Vec<OrderedStudent> = Vec::new();
+
+    for (i, mut students) in applicants.into_iter().enumerate() {
+        if students.is_empty() {
+            continue;
+        }
+        let spots = categories[i].max_placements.saturating_sub(placed[i].len());
+        let accepted = students.len().min(spots);
+
+        sort_by_priority(&mut students, priorities, i);
+        for student in students.drain(accepted..) {
+            waitlists[i].push(student.clone());
+            rejected.push(student);
+        }
+        placed[i].extend(students);
+    }
+
+    rejected.sort();
+    rejected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeSet, VecDeque};
+
+    #[test]
+    fn test_accept_applicants() {
+        let cooking = Category::new("Cooking", 1);
+        let walking = Category::new("Walking", 1);
+
+        let bert = OrderedStudent {
+            name: "Bert".into(),
+            id: 0,
+            preferences: VecDeque::from(vec![1]),
+            exclude: Vec::new(),
+            order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let kate = OrderedStudent {
+            name: "Kate".into(),
+            id: 1,
+            preferences: VecDeque::new(),
+            exclude: Vec::new(),
+            order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let suze = OrderedStudent {
+            name: "Suze".into(),
+            id: 2,
+            preferences: VecDeque::new(),
+            exclude: Vec::new(),
+            order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let harry = OrderedStudent {
+            name: "Harry".into(),
+            id: 3,
+            preferences: VecDeque::new(),
+            exclude: Vec::new(),
+            order: 3,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+
+        let applicants = vec![vec![bert.clone(), kate.clone()], vec![suze.clone()]];
+        let mut placed = vec![Vec::new(), vec![harry.clone()]];
+
+        let categories = vec![cooking, walking];
+        let mut waitlists = vec![Vec::new(); 2];
+        let priorities = Priorities::new(&[], &categories);
+        let rejected = accept_applicants(
+            applicants,
+            &mut placed,
+            &categories,
+            &priorities,
+            &mut waitlists,
+        );
+
+        assert_eq!(placed, vec![vec![kate], vec![harry]]);
+        assert_eq!(rejected, vec![bert.clone(), suze.clone()]);
+        assert_eq!(waitlists, vec![vec![bert], vec![suze]]);
+    }
+
+    #[test]
+    fn test_placements_are_final() {
+        let cooking = Category::new("Cooking", 1);
+        let reading = Category::new("Reading", 1);
+
+        // Bert has a better lot than Kate, but loses category reading to Suze. In the next
+        // round Kate already has her spot in category cooking, so Bert can't take it.
+        let bert = OrderedStudent {
+            name: "Bert".into(),
+            id: 0,
+            preferences: VecDeque::from(vec![1, 0]),
+            exclude: Vec::new(),
+            order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let suze = OrderedStudent {
+            name: "Suze".into(),
+            id: 1,
+            preferences: VecDeque::from(vec![1]),
+            exclude: Vec::new(),
+            order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let kate = OrderedStudent {
+            name: "Kate".into(),
+            id: 2,
+            preferences: VecDeque::from(vec![0]),
+            exclude: Vec::new(),
+            order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+
+        let categories = vec![cooking, reading];
+        let mut placed = vec![Vec::new(); 2];
+        let mut not_placable = Vec::new();
+        let mut waitlists = vec![Vec::new(); 2];
+        let priorities = Priorities::new(&[], &categories);
+
+        let mut unplaced_students = vec![bert, suze, kate];
+        while !unplaced_students.is_empty() {
+            let mut applicants = vec![Vec::new(); 2];
+            place_students(unplaced_students, &mut applicants, &mut not_placable);
+            unplaced_students = accept_applicants(
+                applicants,
+                &mut placed,
+                &categories,
+                &priorities,
+                &mut waitlists,
+            );
+        }
+
+        assert_eq!(placed[1][0].name, "Suze");
+        assert_eq!(placed[0][0].name, "Kate");
+        assert_eq!(not_placable.len(), 1);
+        assert_eq!(not_placable[0].name, "Bert");
+    }
+}
@@ -13,7 +13,21 @@
 //!
 //! # Algorithm
 //!
-//! At this time this library only implements the `Deferred Acceptance - Single Tie Break` algorithm. The library has been designed to make the implementation of other algorithms possible (it just needs to be done ;).
+//! This library implements the following algorithms:
+//!
+//! - `Deferred Acceptance - Single Tie Break` (module `da_stb`): a single lottery decides the order of the students in every category.
+//!   `da_stb::improve_placements` lets students trade the spots they lost to a tie afterwards, keeping the
+//!   result stable.
+//! - `Deferred Acceptance - Multiple Tie Break` (module `da_mtb`): an independent lottery is drawn for every category.
+//! - `Top Trading Cycles` (module `ttc`): students trade spots, which gives a Pareto-efficient result.
+//! - `Immediate Acceptance` or `Boston` (module `immediate_acceptance`): placements in each round are final.
+//! - `Efficiency-Adjusted Deferred Acceptance` (module `eadam`): deferred acceptance, where students that
+//!   consent waive priorities that only hurt others.
+//! - `Probabilistic Serial` (module `probabilistic_serial`): students eat from their preferences at the same
+//!   speed, which gives the probability of every placement, and a match is drawn from these probabilities.
+//!
+//! Module `analysis` checks the result of a match for blocking pairs, to verify that it is stable,
+//! and simulates a match many times to estimate the chances of every student.
 //!
 //! # Usage
 //!
@@ -52,18 +66,18 @@
 //! let mut rng = thread_rng();
 //! let categories = Vec::from([cooking, reading, walking]);
 //!
-//! let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng);
+//! let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng).unwrap();
 //!
 //! println!("Students matched to categories:");
 //! println!();
 //! for category in &categories {
 //!     println!("{}:", &category.name);
-//!     for student in match_result
+//!     for name in match_result
 //!         .placed
 //!         .get(&category.name)
 //!         .unwrap_or(&Vec::new())
 //!     {
-//!         println!(" - {}", &student.name);
+//!         println!(" - {}", name);
 //!     }
 //! }
 //!
@@ -92,6 +106,14 @@
 //! Students are distributed over multiple categories. A single student can be placed
 //! in more than one category.
 //!
+//! Use `match_students_to_multiple_categories_with_options` with `MultipleCategoryOptions`
+//! to limit the number of categories per student (`Student::max_categories` overrides the
+//! limit for a single student), or to only place students in categories from their
+//! preferences after the first round.
+//!
+//! Give categories time slots with `Category::with_time_slot` to make sure no student is
+//! placed in two categories that take place at the same time.
+//!
 //! # Example
 //!
 //! ```
@@ -128,18 +150,20 @@
 //! let match_result = match_students_to_multiple_categories(
 //!     Vec::from([bert, suze]),
 //!     &categories,
-//!     &mut rng);
+//!     &mut rng,
+//! )
+//! .unwrap();
 //!
 //! println!("Students matched to categories:");
 //! println!();
 //! for category in &categories {
 //!     println!("{}:", &category.name);
-//!     for student in match_result
+//!     for name in match_result
 //!         .placed
 //!         .get(&category.name)
 //!         .unwrap_or(&Vec::new())
 //!     {
-//!         println!(" - {}", &student.name);
+//!         println!(" - {}", name);
 //!     }
 //! }
 //!
@@ -165,12 +189,99 @@
 //!
 //! All students could be placed.
 //! ```
+//!
+//! ## Reserved seats
+//!
+//! Give students attributes with `Student::with_attribute` and reserve seats for them with
+//! [`Category::with_reserve`](struct.Category.html#method.with_reserve). When a category has more candidates than seats,
+//! reserved seats go to the best ranked students with the attribute, and the
+//! `reserve_order` of the category decides whether reserved seats are filled before or after
+//! the open seats. Reserved seats that can't be filled are open to everyone. Reserves are
+//! supported by `da_stb` and `da_mtb`.
+//!
+//! ## Students without a placement
+//!
+//! By default, students that can't be placed in any of their preferences are placed in a random
+//! open category they did not exclude. Use `da_stb::match_students_with_fallback` or
+//! `Matcher::match_students_with_fallback` with a `Fallback` policy to leave them unplaced
+//! (`Fallback::None`), to place them in the open category with the lowest cost, like the distance
+//! to a school (`Fallback::LowestCost`), or in the first open category of a second list of
+//! preferences (`Fallback::SecondRound`). The policy is recorded in the result.
+//!
+//! ## Reproducible lottery
+//!
+//! Use a [`Lottery`](struct.Lottery.html) instead of `thread_rng()` when third parties must be
+//! able to reproduce the draw. The lottery is seeded by a public value and the input of the
+//! match, and its seed is recorded in the result.
+//!
+//! ```
+//! use matchmaker::da_stb::match_students;
+//! use matchmaker::{Category, Lottery, Student};
+//! use std::collections::VecDeque;
+//!
+//! let cooking = Category::new("Cooking", 10);
+//! let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new());
+//!
+//! let students = Vec::from([bert]);
+//! let categories = Vec::from([cooking]);
+//!
+//! let lottery = Lottery::new("AEX 2020-09-01 612.43", &students, &categories);
+//! let match_result = lottery.draw(students, &categories, match_students).unwrap();
+//!
+//! println!("Seed: {}", match_result.seed.unwrap());
+//! ```
+//!
+//! ## Choosing a mechanism
+//!
+//! Every mechanism implements the `Matcher` trait, so code can match, match to multiple
+//! categories and simulate without knowing the mechanism. `Mechanism` names every mechanism
+//! and can be read from a configuration value like `"da-mtb"`.
+//!
+//! ```
+//! use matchmaker::{Category, Matcher, Mechanism, MultipleCategoryOptions, Student};
+//! use rand::thread_rng;
+//! use std::collections::VecDeque;
+//!
+//! let mechanism = Mechanism::Ttc;
+//!
+//! let cooking = Category::new("Cooking", 1);
+//! let reading = Category::new("Reading", 1);
+//! let bert = Student::new(
+//!     "Bert",
+//!     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+//!     Vec::new(),
+//! );
+//!
+//! let match_result = mechanism
+//!     .match_students_to_multiple_categories(
+//!         Vec::from([bert]),
+//!         &[cooking, reading],
+//!         &MultipleCategoryOptions::default(),
+//!         &mut thread_rng(),
+//!     )
+//!     .unwrap();
+//!
+//! assert_eq!(match_result.placements["Bert"].len(), 2);
+//! ```
 use core::fmt::Debug;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 
+pub mod analysis;
+pub mod da_mtb;
 pub mod da_stb;
+pub mod eadam;
+pub mod immediate_acceptance;
+mod lottery;
+mod matcher;
+pub mod probabilistic_serial;
+pub mod ttc;
+mod validation;
+
+pub use lottery::Lottery;
+pub use matcher::{Matcher, Mechanism};
+pub use validation::{validate, MatchError, Violation};
 
 /// Holds a student
 #[derive(Debug, Eq, Clone, Deserialize, Serialize)]
@@ -181,6 +292,26 @@ pub struct Student {
     pub preferences: VecDeque<Category>,
     /// Categories the student wishes *not* to be placed in
     pub exclude: Vec<Category>,
+    /// Sizes of the groups of equally preferred categories in `preferences`, in order of
+    /// preference. Empty when every preference is strict.
+    #[serde(default)]
+    pub indifference_groups: Vec<usize>,
+    /// Group the student wishes to be placed together with, if any
+    #[serde(default)]
+    pub group: Option<Group>,
+    /// Number of categories the student can be placed in when matching to multiple
+    /// categories, or `None` to use [`MultipleCategoryOptions::max_categories`]
+    ///
+    /// [`MultipleCategoryOptions::max_categories`]: struct.MultipleCategoryOptions.html#structfield.max_categories
+    #[serde(default)]
+    pub max_categories: Option<usize>,
+    /// Attributes of the student, like `low-income`, that can entitle it to reserved seats
+    #[serde(default)]
+    pub attributes: BTreeSet<String>,
+    /// Whether the student consents to waive its priority in a category when that can't
+    /// change its own placement, see [`eadam`](eadam/index.html)
+    #[serde(default)]
+    pub consent: bool,
 }
 
 impl Student {
@@ -231,12 +362,229 @@ impl Student {
     /// );
     /// ```
     pub fn new(name: &str, preferences: VecDeque<Category>, exclude: Vec<Category>) -> Self {
-        Student {
+        let mut student = Student {
             name: name.into(),
             preferences,
             exclude,
+            indifference_groups: Vec::new(),
+            group: None,
+            max_categories: None,
+            attributes: BTreeSet::new(),
+            consent: false,
+        };
+        student.clear_priorities();
+        student
+    }
+
+    /// Return a new Student with groups of equally preferred categories
+    ///
+    /// Ties within a group are broken by the lottery. The preference rank of a placement is
+    /// the rank of its group.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - A &`str` that holds the name of the student (must be unique)
+    /// * `groups` - A `Vec` of groups of [`Category`]s the student wishes to be placed in, in order of preference
+    /// * `exclude` - A `Vec` of [`Category`]s the student wishes *not* to be placed in
+    ///
+    /// [`Category`]: struct.Category.html
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use matchmaker::{Category, Student};
+    ///
+    /// let cooking = Category::new("Cooking", 10);
+    /// let reading = Category::new("Reading", 10);
+    /// let walking = Category::new("Walking", 5);
+    ///
+    /// // Bert doesn't care whether he is placed in cooking or reading,
+    /// // as long as it isn't walking
+    /// let bert = Student::with_indifference_groups(
+    ///     "Bert",
+    ///     vec![vec![cooking, reading], vec![walking.clone()]],
+    ///     Vec::new(),
+    /// );
+    ///
+    /// assert_eq!(bert.preference_rank("Reading"), Some(1));
+    /// assert_eq!(bert.preference_rank("Walking"), Some(2));
+    /// ```
+    pub fn with_indifference_groups(
+        name: &str,
+        groups: Vec<Vec<Category>>,
+        exclude: Vec<Category>,
+    ) -> Self {
+        let groups: Vec<Vec<Category>> = groups.into_iter().filter(|g| !g.is_empty()).collect();
+
+        let mut student = Student {
+            name: name.into(),
+            indifference_groups: groups.iter().map(|g| g.len()).collect(),
+            preferences: groups.into_iter().flatten().collect(),
+            exclude,
+            group: None,
+            max_categories: None,
+            attributes: BTreeSet::new(),
+            consent: false,
+        };
+        student.clear_priorities();
+        student
+    }
+
+    /// Return the Student as a member of a group
+    ///
+    /// Members of a group must have the same preferences. They enter the lottery once and are
+    /// placed in the same category, taking a spot each. Only [`da_stb`] keeps groups together,
+    /// the other mechanisms reject them.
+    ///
+    /// # Arguments
+    ///
+    /// * `group` - The [`Group`] the student belongs to
+    ///
+    /// [`Group`]: struct.Group.html
+    /// [`da_stb`]: da_stb/index.html
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use matchmaker::{Category, Group, Student};
+    /// use std::collections::VecDeque;
+    ///
+    /// let cooking = Category::new("Cooking", 10);
+    ///
+    /// // Bert and Suze are siblings and are only placed together
+    /// let siblings = Group::hard("Bert and Suze");
+    /// let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new())
+    ///     .with_group(siblings.clone());
+    /// let suze = Student::new("Suze", VecDeque::from(vec![cooking]), Vec::new())
+    ///     .with_group(siblings);
+    /// ```
+    pub fn with_group(mut self, group: Group) -> Self {
+        self.group = Some(group);
+        self
+    }
+
+    /// Return the Student with a limit on the number of categories it can be placed in when
+    /// matching to multiple categories
+    ///
+    /// # Arguments
+    ///
+    /// * `max_categories` - Maximum number of categories the student can be placed in
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use matchmaker::{Category, Student};
+    /// use std::collections::VecDeque;
+    ///
+    /// let cooking = Category::new("Cooking", 10);
+    /// let reading = Category::new("Reading", 10);
+    ///
+    /// // Bert likes both categories, but only has time for one
+    /// let bert = Student::new("Bert", VecDeque::from(vec![cooking, reading]), Vec::new())
+    ///     .with_max_categories(1);
+    /// ```
+    pub fn with_max_categories(mut self, max_categories: usize) -> Self {
+        self.max_categories = Some(max_categories);
+        self
+    }
+
+    /// Return the Student with an attribute
+    ///
+    /// Categories can reserve seats for students with an attribute, see
+    /// [`Category::with_reserve`].
+    ///
+    /// # Arguments
+    ///
+    /// * `attribute` - Attribute of the student, like `low-income` or `district-north`
+    ///
+    /// [`Category::with_reserve`]: struct.Category.html#method.with_reserve
+    pub fn with_attribute(mut self, attribute: &str) -> Self {
+        self.attributes.insert(attribute.into());
+        self
+    }
+
+    /// Return the Student with its consent to waive priority in categories where the
+    /// priority can't change its own placement, see [`eadam`](eadam/index.html)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use matchmaker::{Category, Student};
+    /// use std::collections::VecDeque;
+    ///
+    /// let cooking = Category::new("Cooking", 10);
+    ///
+    /// let bert = Student::new("Bert", VecDeque::from(vec![cooking]), Vec::new()).with_consent();
+    /// assert!(bert.consent);
+    /// ```
+    pub fn with_consent(mut self) -> Self {
+        self.consent = true;
+        self
+    }
+
+    /// Return the rank of a category in the preferences of the student (`1` is the first
+    /// choice), or `None` when the student did not list the category. Equally preferred
+    /// categories share a rank.
+    ///
+    /// # Arguments
+    ///
+    /// * `category_name` - Name of the category
+    pub fn preference_rank(&self, category_name: &str) -> Option<usize> {
+        self.preferences
+            .iter()
+            .zip(self.preference_ranks())
+            .find(|(category, _)| category.name == category_name)
+            .map(|(_, rank)| rank)
+    }
+
+    /// Remove a category from the preferences, keeping the indifference groups intact
+    fn remove_preference(&mut self, category_name: &str) {
+        let index = match self
+            .preferences
+            .iter()
+            .position(|c| c.name == category_name)
+        {
+            Some(index) => index,
+            None => return,
+        };
+        self.preferences.remove(index);
+
+        if !self.indifference_groups.is_empty() {
+            let mut end = 0;
+            for size in self.indifference_groups.iter_mut() {
+                end += *size;
+                if index < end {
+                    *size -= 1;
+                    break;
+                }
+            }
+            self.indifference_groups.retain(|&size| size > 0);
+            if self.indifference_groups.iter().all(|&size| size == 1) {
+                self.indifference_groups.clear();
+            }
+        }
+    }
+
+    /// Drop the priorities of the categories the student lists, as a match takes priorities
+    /// from its own categories, so copies of the student stay small
+    pub(crate) fn clear_priorities(&mut self) {
+        for category in self.preferences.iter_mut().chain(self.exclude.iter_mut()) {
+            category.priorities.clear();
         }
     }
+
+    /// Return the rank of every preference, in order of preference
+    fn preference_ranks(&self) -> VecDeque<usize> {
+        if self.indifference_groups.is_empty() {
+            return (1..=self.preferences.len()).collect();
+        }
+
+        self.indifference_groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &size)| std::iter::repeat_n(i + 1, size))
+            .collect()
+    }
 }
 
 impl PartialEq for Student {
@@ -245,16 +593,6 @@ impl PartialEq for Student {
     }
 }
 
-impl From<OrderedStudent> for Student {
-    fn from(os: OrderedStudent) -> Self {
-        Student {
-            name: os.name,
-            preferences: os.preferences,
-            exclude: os.exclude,
-        }
-    }
-}
-
 impl Ord for Student {
     fn cmp(&self, other: &Self) -> Ordering {
         self.name.cmp(&other.name)
@@ -267,12 +605,288 @@ impl PartialOrd for Student {
     }
 }
 
+/// Holds a group of students that wish to be placed in the same category
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Group {
+    /// Name of the group (must be unique)
+    pub name: String,
+    /// When `true`, the members are only placed together. Otherwise members that can't be
+    /// placed together based on their preferences are placed on their own, and no longer
+    /// have a group in the result.
+    pub hard: bool,
+}
+
+impl Group {
+    /// Return a new `Group` whose members are only placed together
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the group (must be unique)
+    pub fn hard(name: &str) -> Self {
+        Group {
+            name: name.into(),
+            hard: true,
+        }
+    }
+
+    /// Return a new `Group` whose members are placed on their own when they can't be placed
+    /// together
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the group (must be unique)
+    pub fn soft(name: &str) -> Self {
+        Group {
+            name: name.into(),
+            hard: false,
+        }
+    }
+}
+
+/// Holds the options for matching students to more than one category
+///
+/// The default places students in as many categories as they can get, including
+/// categories they did not list in their preferences.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MultipleCategoryOptions {
+    /// Number of categories a student can be placed in, unless the student sets its own
+    /// `max_categories`. `None` means there is no limit.
+    #[serde(default)]
+    pub max_categories: Option<usize>,
+    /// When `true`, students are only placed in categories from their preferences after the
+    /// first round. The first round may still place a student in any category it didn't
+    /// exclude when none of its preferences has a spot left.
+    #[serde(default)]
+    pub preferences_only: bool,
+    /// Policy for students that can't be placed in their preferences, in every round
+    #[serde(default)]
+    pub fallback: Fallback,
+}
+
+/// Policy for students that could not be placed in any of their preferences
+///
+/// Students are placed in order of their lot, and never in a category they exclude or a
+/// category without a spot for their whole group. Students that can't be placed by the
+/// policy end up in `not_placable`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Fallback {
+    /// Leave the students unplaced
+    None,
+    /// Place the students in a random open category
+    #[default]
+    Random,
+    /// Place the students in the open category with the lowest cost, like the distance to a
+    /// school. Holds the costs per category name per student name; categories without a cost
+    /// for the student are not an option.
+    LowestCost(BTreeMap<String, BTreeMap<String, u32>>),
+    /// Place the students in the first open category of a new list of preferences. Holds the
+    /// category names per student name, in order of preference.
+    SecondRound(BTreeMap<String, Vec<String>>),
+}
+
+/// Students per category index
+type StudentsPerCategory = Vec<Vec<OrderedStudent>>;
+
+/// Holds a student during a match
+///
+/// Categories are referred to by their index in the categories of the match, so the
+/// mechanisms never clone or compare whole categories.
 #[derive(Debug, PartialEq, Eq, Clone)]
 struct OrderedStudent {
     name: String,
-    preferences: VecDeque<Category>,
-    exclude: Vec<Category>,
+    /// Index of the student in the students of the match
+    id: usize,
+    /// Indices of the categories the student did not get to yet, in order of preference
+    preferences: VecDeque<usize>,
+    /// Indices of the excluded categories, sorted
+    exclude: Vec<usize>,
     order: usize,
+    /// Rank of the last preference taken from `preferences`, `None` before the first one
+    /// or when the student was assigned randomly
+    rank: Option<usize>,
+    /// Rank of every preference in `preferences`. When empty, every preference ranks one
+    /// below the previous one.
+    preference_ranks: VecDeque<usize>,
+    group: Option<Group>,
+    attributes: BTreeSet<String>,
+}
+
+/// Map the names of the categories of a match to their index, built once per match
+fn category_indices(categories: &[Category]) -> HashMap<&str, usize> {
+    categories
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (c.name.as_str(), i))
+        .collect()
+}
+
+/// Priorities of the students of a match per category index, looked up by the id of the
+/// student, so sorting students doesn't look up their names
+///
+/// Only the students a category lists are kept, as most students have no priority in most
+/// categories of a large match.
+pub(crate) struct Priorities {
+    /// Priority per student id for every category
+    students: Vec<HashMap<usize, usize>>,
+    /// Highest priority of the members of a group per group index for every category
+    groups: Vec<HashMap<usize, usize>>,
+    /// Index of the group of every student
+    group_indices: Vec<Option<usize>>,
+}
+
+impl Priorities {
+    /// Resolve the priorities of the categories once, where the id of a student is its index
+    fn new(students: &[Student], categories: &[Category]) -> Self {
+        let ids: HashMap<&str, usize> = students
+            .iter()
+            .enumerate()
+            .map(|(id, s)| (s.name.as_str(), id))
+            .collect();
+        let mut group_names: HashMap<&str, usize> = HashMap::new();
+        let group_indices: Vec<Option<usize>> = students
+            .iter()
+            .map(|s| {
+                s.group.as_ref().map(|group| {
+                    let count = group_names.len();
+                    *group_names.entry(group.name.as_str()).or_insert(count)
+                })
+            })
+            .collect();
+
+        let mut priorities = Priorities {
+            students: Vec::with_capacity(categories.len()),
+            groups: Vec::with_capacity(categories.len()),
+            group_indices,
+        };
+        for category in categories.iter() {
+            let mut student_priorities: HashMap<usize, usize> = HashMap::new();
+            let mut group_priorities: HashMap<usize, usize> = HashMap::new();
+
+            for (name, &priority) in category.priorities.iter() {
+                if let Some(&id) = ids.get(name.as_str()) {
+                    student_priorities.insert(id, priority);
+                    if let Some(group) = priorities.group_indices[id] {
+                        let group_priority = group_priorities.entry(group).or_default();
+                        *group_priority = (*group_priority).max(priority);
+                    }
+                }
+            }
+
+            priorities.students.push(student_priorities);
+            priorities.groups.push(group_priorities);
+        }
+        priorities
+    }
+
+    /// Return the priority of a student for the category with this index, which members of
+    /// a group share with the member with the highest priority
+    fn get(&self, category: usize, student: &OrderedStudent) -> usize {
+        let priority = match (&student.group, self.group_indices.get(student.id)) {
+            (Some(_), Some(&Some(group))) => self.groups[category].get(&group),
+            _ => self.students[category].get(&student.id),
+        };
+        priority.copied().unwrap_or(0)
+    }
+}
+
+impl OrderedStudent {
+    /// Categories that are not part of the match are left out, keeping the ranks of the others.
+    fn new(student: &Student, id: usize, order: usize, indices: &HashMap<&str, usize>) -> Self {
+        let (preferences, preference_ranks) = student
+            .preferences
+            .iter()
+            .zip(student.preference_ranks())
+            .filter_map(|(c, rank)| indices.get(c.name.as_str()).map(|&i| (i, rank)))
+            .unzip();
+        let mut exclude: Vec<usize> = student
+            .exclude
+            .iter()
+            .filter_map(|c| indices.get(c.name.as_str()).copied())
+            .collect();
+        exclude.sort_unstable();
+        exclude.dedup();
+
+        OrderedStudent {
+            name: student.name.clone(),
+            id,
+            preferences,
+            exclude,
+            order,
+            rank: None,
+            preference_ranks,
+            group: student.group.clone(),
+            attributes: student.attributes.clone(),
+        }
+    }
+
+    /// Return whether the student excludes the category with this index
+    fn excludes(&self, category: usize) -> bool {
+        self.exclude.binary_search(&category).is_ok()
+    }
+
+    /// Return what the student is placed as: its group, or the student on its own
+    fn unit(&self) -> (bool, &str) {
+        match &self.group {
+            Some(group) => (true, &group.name),
+            None => (false, &self.name),
+        }
+    }
+
+    /// Take the next preference and keep track of its rank
+    fn next_preference(&mut self) -> Option<usize> {
+        let category = self.preferences.pop_front()?;
+        self.rank = match self.preference_ranks.pop_front() {
+            Some(rank) => Some(rank),
+            None => Some(self.rank.map_or(1, |rank| rank + 1)),
+        };
+        Some(category)
+    }
+
+    /// Shuffle every group of equally preferred categories
+    fn break_ties(&mut self, mut rng: &mut impl rand::Rng) {
+        use rand::seq::SliceRandom;
+
+        let preferences = self.preferences.make_contiguous();
+        let mut start = 0;
+        while start < preferences.len() {
+            let rank = self.preference_ranks.get(start);
+            let end = start
+                + self
+                    .preference_ranks
+                    .iter()
+                    .skip(start)
+                    .take_while(|r| Some(*r) == rank)
+                    .count()
+                    .max(1);
+            preferences[start..end].shuffle(&mut rng);
+            start = end;
+        }
+    }
+
+    /// Remove a category from the preferences, keeping the rank of the other preferences
+    fn remove_preference(&mut self, category: usize) {
+        if let Some(index) = self.preferences.iter().position(|&c| c == category) {
+            self.preferences.remove(index);
+            if index < self.preference_ranks.len() {
+                self.preference_ranks.remove(index);
+            }
+        }
+    }
+
+    /// Move a category to the front of its group of equally preferred categories
+    fn prefer_within_group(&mut self, category: usize) {
+        let index = match self.preferences.iter().position(|&c| c == category) {
+            Some(index) => index,
+            None => return,
+        };
+        let rank = self.preference_ranks.get(index);
+        let start = self
+            .preference_ranks
+            .iter()
+            .position(|r| Some(r) == rank)
+            .unwrap_or(index);
+        self.preferences.make_contiguous()[start..=index].rotate_right(1);
+    }
 }
 
 impl Ord for OrderedStudent {
@@ -294,6 +908,33 @@ pub struct Category {
     pub name: String,
     /// Maximum number of students that can be placed in category this category
     pub max_placements: usize,
+    /// Minimum number of students the category needs. A category with fewer students is
+    /// cancelled and its students are matched again. Defaults to `0`.
+    #[serde(default)]
+    pub min_placements: usize,
+    /// Priority per student name. When there are more candidates than placements, students with
+    /// a higher priority are placed first and the lottery only breaks ties between students
+    /// with the same priority. Students that are not listed have priority `0`. Only the
+    /// categories given to a match count: the copies in the preferences of a [`Student`] don't
+    /// keep their priorities.
+    ///
+    /// [`Student`]: struct.Student.html
+    #[serde(default)]
+    pub priorities: BTreeMap<String, usize>,
+    /// Times at which the category takes place. When students are placed in multiple
+    /// categories, no student is placed in two categories with overlapping time slots.
+    #[serde(default)]
+    pub time_slots: Vec<TimeSlot>,
+    /// Seats reserved for students with an attribute
+    #[serde(default)]
+    pub reserves: Vec<Reserve>,
+    /// Whether reserved seats are filled before or after the open seats
+    #[serde(default)]
+    pub reserve_order: ReserveOrder,
+    /// Names of the students that are placed in the category before the match. They count
+    /// against `max_placements` and are never moved.
+    #[serde(default)]
+    pub fixed_placements: Vec<String>,
 }
 
 impl Category {
@@ -319,8 +960,269 @@ impl Category {
         Category {
             name: name.into(),
             max_placements,
+            min_placements: 0,
+            priorities: BTreeMap::new(),
+            time_slots: Vec::new(),
+            reserves: Vec::new(),
+            reserve_order: ReserveOrder::default(),
+            fixed_placements: Vec::new(),
         }
     }
+
+    /// Return the `Category` with a minimum number of students
+    ///
+    /// When the category doesn't reach the minimum, it is cancelled and its students are
+    /// matched again with the same lottery. Categories are cancelled one at a time, starting
+    /// with the category with the fewest students.
+    ///
+    /// # Arguments
+    ///
+    /// * `min_placements` - Minimum number of students the category needs
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use matchmaker::da_stb::match_students;
+    /// use matchmaker::{Category, Student};
+    /// use rand::thread_rng;
+    /// use std::collections::VecDeque;
+    ///
+    /// // Cooking only goes ahead with at least 2 students
+    /// let cooking = Category::new("Cooking", 10).with_min_placements(2);
+    /// let reading = Category::new("Reading", 10);
+    ///
+    /// let bert = Student::new(
+    ///     "Bert",
+    ///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+    ///     Vec::new(),
+    /// );
+    ///
+    /// let mut rng = thread_rng();
+    /// let categories = Vec::from([cooking, reading]);
+    ///
+    /// let match_result = match_students(Vec::from([bert]), &categories, &mut rng).unwrap();
+    ///
+    /// assert_eq!(match_result.cancelled, vec!["Cooking".to_string()]);
+    /// assert_eq!(match_result.placed["Reading"].len(), 1);
+    /// ```
+    pub fn with_min_placements(mut self, min_placements: usize) -> Self {
+        self.min_placements = min_placements;
+        self
+    }
+
+    /// Return the `Category` with a priority for a student
+    ///
+    /// # Arguments
+    ///
+    /// * `student_name` - Name of the student
+    /// * `priority` - Priority of the student (higher is placed first, default is `0`)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use matchmaker::Category;
+    ///
+    /// // Siblings of current students are placed before neighbourhood children,
+    /// // who are placed before everyone else
+    /// let school = Category::new("Sunflower", 30)
+    ///     .with_priority("Bert", 2)
+    ///     .with_priority("Suze", 1);
+    ///
+    /// assert_eq!(school.priority("Bert"), 2);
+    /// assert_eq!(school.priority("Kate"), 0);
+    /// ```
+    pub fn with_priority(mut self, student_name: &str, priority: usize) -> Self {
+        self.priorities.insert(student_name.into(), priority);
+        self
+    }
+
+    /// Return the priority of a student for this category
+    ///
+    /// # Arguments
+    ///
+    /// * `student_name` - Name of the student
+    pub fn priority(&self, student_name: &str) -> usize {
+        self.priorities.get(student_name).copied().unwrap_or(0)
+    }
+
+    /// Return the `Category` with a time at which it takes place
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Start of the time slot
+    /// * `end` - End of the time slot (exclusive)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use matchmaker::Category;
+    ///
+    /// // Times in minutes since Monday 00:00
+    /// const DAY: u32 = 24 * 60;
+    ///
+    /// // Cooking on Monday and Wednesday from 14:00 to 15:30
+    /// let cooking = Category::new("Cooking", 10)
+    ///     .with_time_slot(14 * 60, 15 * 60 + 30)
+    ///     .with_time_slot(2 * DAY + 14 * 60, 2 * DAY + 15 * 60 + 30);
+    /// // Reading on Monday from 15:00 to 16:00
+    /// let reading = Category::new("Reading", 10).with_time_slot(15 * 60, 16 * 60);
+    /// // Walking on Monday from 15:30 to 17:00
+    /// let walking = Category::new("Walking", 10).with_time_slot(15 * 60 + 30, 17 * 60);
+    ///
+    /// assert!(cooking.overlaps(&reading));
+    /// assert!(!cooking.overlaps(&walking));
+    /// ```
+    pub fn with_time_slot(mut self, start: u32, end: u32) -> Self {
+        self.time_slots.push(TimeSlot { start, end });
+        self
+    }
+
+    /// Return `true` when a time slot of this category overlaps a time slot of the other
+    /// category
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The other category
+    pub fn overlaps(&self, other: &Category) -> bool {
+        self.time_slots
+            .iter()
+            .any(|slot| other.time_slots.iter().any(|o| slot.overlaps(o)))
+    }
+
+    /// Return the `Category` with seats reserved for students with an attribute
+    ///
+    /// When there are more candidates than placements, reserved seats go to the students with
+    /// the attribute that have the highest priority or the best lot. Reserved seats that
+    /// can't be filled by students with the attribute are open to everyone. A student is
+    /// never placed on more than one seat, so the order of the reserves and
+    /// [`reserve_order`](#structfield.reserve_order) can matter.
+    ///
+    /// Reserves are supported by [`da_stb`] and [`da_mtb`].
+    ///
+    /// [`da_stb`]: da_stb/index.html
+    /// [`da_mtb`]: da_mtb/index.html
+    ///
+    /// # Arguments
+    ///
+    /// * `attribute` - Attribute a student needs for a reserved seat
+    /// * `seats` - Number of reserved seats
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use matchmaker::{Category, ReserveOrder};
+    ///
+    /// // At least 30% of the seats for low-income students, filled after the open seats
+    /// let school = Category::new("Sunflower", 30)
+    ///     .with_reserve("low-income", 30 * 30 / 100)
+    ///     .with_reserve_order(ReserveOrder::OpenFirst);
+    /// ```
+    pub fn with_reserve(mut self, attribute: &str, seats: usize) -> Self {
+        self.reserves.push(Reserve {
+            attribute: attribute.into(),
+            seats,
+        });
+        self
+    }
+
+    /// Return the `Category` with the order in which reserved and open seats are filled
+    ///
+    /// # Arguments
+    ///
+    /// * `reserve_order` - Whether reserved seats are filled before or after the open seats
+    pub fn with_reserve_order(mut self, reserve_order: ReserveOrder) -> Self {
+        self.reserve_order = reserve_order;
+        self
+    }
+
+    /// Return the `Category` with a student that is placed in it before the match
+    ///
+    /// The student takes a seat, whatever its preferences, lot or priority, and is never
+    /// moved. The other students are matched to the seats that are left. A category with
+    /// fixed placements is never cancelled. The student must be part of the match and must not
+    /// exclude the category.
+    ///
+    /// Fixed placements are supported by [`da_stb`].
+    ///
+    /// [`da_stb`]: da_stb/index.html
+    ///
+    /// # Arguments
+    ///
+    /// * `student_name` - Name of the student
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use matchmaker::da_stb::match_students;
+    /// use matchmaker::{Category, Student};
+    /// use rand::thread_rng;
+    /// use std::collections::VecDeque;
+    ///
+    /// // Bert returns to cooking, which leaves no seat for Suze
+    /// let cooking = Category::new("Cooking", 1).with_fixed_placement("Bert");
+    /// let reading = Category::new("Reading", 1);
+    ///
+    /// let bert = Student::new("Bert", VecDeque::from(vec![reading.clone()]), Vec::new());
+    /// let suze = Student::new("Suze", VecDeque::from(vec![cooking.clone()]), Vec::new());
+    ///
+    /// let mut rng = thread_rng();
+    /// let categories = Vec::from([cooking, reading]);
+    ///
+    /// let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng).unwrap();
+    ///
+    /// assert_eq!(match_result.placed["Cooking"][0], "Bert");
+    /// assert!(match_result.placements["Bert"][0].fixed);
+    /// assert_eq!(match_result.placed["Reading"][0], "Suze");
+    /// ```
+    pub fn with_fixed_placement(mut self, student_name: &str) -> Self {
+        self.fixed_placements.push(student_name.into());
+        self
+    }
+}
+
+/// Holds a number of seats in a category reserved for students with an attribute
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Reserve {
+    /// Attribute a student needs for a reserved seat
+    pub attribute: String,
+    /// Number of reserved seats
+    pub seats: usize,
+}
+
+/// Order in which the reserved and open seats of a category are filled
+///
+/// Filling the open seats first leaves the reserved seats for students with the attribute
+/// that did not get an open seat, which favours these students more than filling the
+/// reserved seats first.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ReserveOrder {
+    /// Fill the reserved seats first, then the open seats
+    #[default]
+    ReservedFirst,
+    /// Fill the open seats first, then the reserved seats
+    OpenFirst,
+}
+
+/// Holds a time at which a category takes place
+///
+/// Use the same unit for every category, like minutes since the start of the week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TimeSlot {
+    /// Start of the time slot
+    pub start: u32,
+    /// End of the time slot (exclusive), must be after `start`
+    pub end: u32,
+}
+
+impl TimeSlot {
+    /// Return `true` when the time slots share any time
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The other time slot
+    pub fn overlaps(&self, other: &TimeSlot) -> bool {
+        self.start < other.end && other.start < self.end
+    }
 }
 
 impl std::hash::Hash for Category {
@@ -343,36 +1245,135 @@ impl Debug for Category {
     }
 }
 
+/// Holds the details of a single placement
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Placement {
+    /// Name of the category the student is placed in
+    pub category: String,
+    /// Lottery number that decided this placement (starting at `1`, lower numbers go first)
+    pub lottery_number: usize,
+    /// Rank of the preference the student is placed on (`1` is the first choice), or `None`
+    /// when the student was randomly assigned to this category
+    pub preference_rank: Option<usize>,
+    /// Whether the placement was fixed before the match
+    #[serde(default)]
+    pub fixed: bool,
+}
+
 /// Holds the result of a match
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct MatchResult {
-    /// Hashmap containing a list of placed students per category name
-    pub placed: HashMap<String, Vec<Student>>,
-    /// List of students that could not be placed in any category
-    pub not_placable: Vec<Student>,
+    /// Map containing the names of the placed students per category name
+    pub placed: BTreeMap<String, Vec<String>>,
+    /// Names of the students that could not be placed in any category
+    pub not_placable: Vec<String>,
+    /// Map containing the lottery number (starting at `1`, lower numbers go first) per student name.
+    /// When students are placed in multiple categories, this is the lottery number of the first round.
+    /// With a lottery per category, this is the lottery number used for random assignment.
+    #[serde(default)]
+    pub lottery_numbers: BTreeMap<String, usize>,
+    /// Map containing the lottery number per category name per student name, for mechanisms
+    /// that draw a lottery per category. Holds the categories the student listed or was placed
+    /// in. Empty when a single lottery decides every category.
+    #[serde(default)]
+    pub category_lottery_numbers: BTreeMap<String, BTreeMap<String, usize>>,
+    /// Map containing the placements per student name
+    #[serde(default)]
+    pub placements: BTreeMap<String, Vec<Placement>>,
+    /// Map containing a waitlist per category name. A waitlist holds the names of the students
+    /// that were rejected by the category, ordered by priority and lottery. Only filled by
+    /// mechanisms that reject students.
+    #[serde(default)]
+    pub waitlists: BTreeMap<String, Vec<String>>,
+    /// Hex encoded seed of the [`Lottery`] that drew this result, or `None` when another random
+    /// number generator was used
+    ///
+    /// [`Lottery`]: struct.Lottery.html
+    #[serde(default)]
+    pub seed: Option<String>,
+    /// Hex encoded hash of the input of the [`Lottery`] that drew this result, used to check
+    /// the input when the result is replayed
+    ///
+    /// [`Lottery`]: struct.Lottery.html
+    #[serde(default)]
+    pub input_hash: Option<String>,
+    /// Names of the categories that were cancelled because they did not reach their minimum
+    /// number of students, in order of cancellation
+    #[serde(default)]
+    pub cancelled: Vec<String>,
+    /// Policy that placed the students that could not be placed in any of their preferences
+    #[serde(default)]
+    pub fallback: Fallback,
+    /// Map containing the names of the categories in which a student waived its priority per
+    /// student name. Only filled by [`eadam`](eadam/index.html).
+    #[serde(default)]
+    pub waivers: BTreeMap<String, Vec<String>>,
 }
 
 impl MatchResult {
+    /// Collect the result of a match, where `placed` and `waitlists` hold the students per
+    /// index of `categories`
     fn from(
-        mut placed: HashMap<String, Vec<OrderedStudent>>,
+        placed: StudentsPerCategory,
         not_placable: Vec<OrderedStudent>,
+        waitlists: StudentsPerCategory,
+        categories: &[Category],
     ) -> Self {
-        let mut new_placed = HashMap::with_capacity(placed.capacity());
-        let mut new_not_placable = Vec::with_capacity(not_placable.capacity());
+        let mut new_placed = BTreeMap::new();
+        let mut new_not_placable = Vec::with_capacity(not_placable.len());
+        let mut lottery_numbers = BTreeMap::new();
+        let mut placements = BTreeMap::new();
 
-        for (key, value) in placed.iter_mut() {
-            let ordered_students = std::mem::replace(value, Vec::new());
-            let students: Vec<Student> = ordered_students.into_iter().map(|os| os.into()).collect();
-            new_placed.insert(key.clone(), students);
+        for (category, ordered_students) in categories.iter().zip(placed) {
+            if ordered_students.is_empty() {
+                continue;
+            }
+            let names: Vec<String> = ordered_students
+                .into_iter()
+                .map(|os| {
+                    lottery_numbers.insert(os.name.clone(), os.order + 1);
+                    placements
+                        .entry(os.name.clone())
+                        .or_insert_with(Vec::new)
+                        .push(Placement {
+                            category: category.name.clone(),
+                            lottery_number: os.order + 1,
+                            preference_rank: os.rank,
+                            fixed: false,
+                        });
+                    os.name
+                })
+                .collect();
+            new_placed.insert(category.name.clone(), names);
         }
 
         for np in not_placable.into_iter() {
-            new_not_placable.push(np.into());
+            lottery_numbers.insert(np.name.clone(), np.order + 1);
+            new_not_placable.push(np.name);
         }
 
+        let waitlists = categories
+            .iter()
+            .zip(waitlists)
+            .filter(|(_, waitlist)| !waitlist.is_empty())
+            .map(|(category, waitlist)| {
+                let names = waitlist.into_iter().map(|os| os.name).collect();
+                (category.name.clone(), names)
+            })
+            .collect();
+
         MatchResult {
             placed: new_placed,
             not_placable: new_not_placable,
+            lottery_numbers,
+            category_lottery_numbers: BTreeMap::new(),
+            placements,
+            waitlists,
+            seed: None,
+            input_hash: None,
+            cancelled: Vec::new(),
+            fallback: Fallback::default(),
+            waivers: BTreeMap::new(),
         }
     }
 }
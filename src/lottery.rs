@@ -0,0 +1,553 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Implements a lottery that can be reproduced by third parties
+
+use super::{Category, Fallback, MatchError, MatchResult, ReserveOrder, Student, Violation};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+/// Random number generator for a match that anyone can reproduce
+///
+/// The seed of the lottery is the SHA-256 hash of a public value and the input of the match,
+/// so nobody can choose the outcome of the draw in advance. Numbers are drawn with ChaCha20.
+/// Anyone with the seed, the input and the same version of this library can re-run the match
+/// and get the same result.
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::da_stb::match_students;
+/// use matchmaker::{Category, Fallback, Lottery, Student};
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let reading = Category::new("Reading", 1);
+///
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// let students = Vec::from([bert, suze]);
+/// let categories = Vec::from([cooking, reading]);
+///
+/// // Use a value that is published after the registration closes, like a closing price
+/// let lottery = Lottery::new("AEX 2020-09-01 612.43", &students, &categories);
+/// let seed = lottery.seed();
+/// let match_result = lottery
+///     .draw(students.clone(), &categories, match_students)
+///     .unwrap();
+/// assert_eq!(match_result.seed, Some(seed.clone()));
+///
+/// // Anyone can reproduce the result with the published seed and input hash
+/// let input_hash = match_result.input_hash.clone().unwrap();
+/// let lottery =
+///     Lottery::replay(&seed, &input_hash, &students, &categories, &Fallback::Random).unwrap();
+/// let reproduced = lottery.draw(students, &categories, match_students).unwrap();
+/// assert_eq!(reproduced.placed, match_result.placed);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Lottery {
+    seed: [u8; 32],
+    input_hash: Option<[u8; 32]>,
+    rng: ChaCha20Rng,
+}
+
+impl Lottery {
+    /// Return a new `Lottery` seeded by a public value and the input of the match
+    ///
+    /// The seed is the SHA-256 hash of the following values. Numbers are written as 64 bit
+    /// little endian integers and strings as their length in bytes followed by their UTF-8
+    /// bytes.
+    ///
+    /// 1. The string `matchmaker lottery v1`
+    /// 2. `public_value`
+    /// 3. The number of categories, followed by each category in the given order: its name,
+    ///    `max_placements`, `min_placements`, the number of priorities, each priority ordered
+    ///    by student name (the student name followed by the priority), the number of time
+    ///    slots, the start and end of each time slot, the number of reserves, the attribute
+    ///    and seats of each reserve, `0` when reserved seats are filled first or `1` when
+    ///    open seats are filled first, and the number of fixed placements followed by the
+    ///    name of each student with a fixed placement
+    /// 4. The number of students, followed by each student in the given order: its name, the
+    ///    number of preferences, the name of each preference, the number of excluded categories,
+    ///    the name of each excluded category, the number of indifference groups, the size of
+    ///    each indifference group, `0` when the student is not part of a group or `1`
+    ///    followed by the name of the group and `1` for a hard group or `0` for a soft group,
+    ///    `0` when the student has no `max_categories` or `1` followed by its value, the
+    ///    number of attributes followed by each attribute in alphabetical order, and `1` when
+    ///    the student consents to waive priority or `0` when it doesn't
+    /// 5. The fallback policy: `0` for `None`, `1` for `Random`, `2` for `LowestCost` followed
+    ///    by the number of students and, ordered by student name, the student name, the number
+    ///    of costs and each cost ordered by category name (the category name followed by the
+    ///    cost), or `3` for `SecondRound` followed by the number of students and, ordered by
+    ///    student name, the student name, the number of categories and the name of each
+    ///    category. `new` uses the default policy, `Random`.
+    ///
+    /// The lottery also hashes the input on its own: the input hash is the SHA-256 hash of the
+    /// string `matchmaker input v1` followed by values 3 to 5. [`draw`] records it in the
+    /// result with the seed, so a [`replay`] can check that the input did not change.
+    ///
+    /// [`draw`]: #method.draw
+    /// [`replay`]: #method.replay
+    ///
+    /// # Arguments
+    ///
+    /// * `public_value` - Value nobody can predict before the input is final, like a closing
+    ///   price or a number drawn by a notary
+    /// * `students` - Students to match
+    /// * `categories` - Categories to match the students to
+    pub fn new(public_value: &str, students: &[Student], categories: &[Category]) -> Self {
+        Self::with_fallback(public_value, students, categories, &Fallback::default())
+    }
+
+    /// Return a new `Lottery` seeded by a public value and the input of a match with a
+    /// fallback policy
+    ///
+    /// The costs or preferences of the policy are part of the input, so they can't be changed
+    /// once the public value is known. See [`new`](#method.new) for the values of the seed.
+    ///
+    /// # Arguments
+    ///
+    /// * `public_value` - Value nobody can predict before the input is final, like a closing
+    ///   price or a number drawn by a notary
+    /// * `students` - Students to match
+    /// * `categories` - Categories to match the students to
+    /// * `fallback` - Policy for students that can't be placed in their preferences
+    pub fn with_fallback(
+        public_value: &str,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+    ) -> Self {
+        let mut hasher = Sha256::new();
+        update_str(&mut hasher, "matchmaker lottery v1");
+        update_str(&mut hasher, public_value);
+        update_input(&mut hasher, students, categories, fallback);
+
+        let mut seed = [0; 32];
+        seed.copy_from_slice(&hasher.finalize());
+        Lottery {
+            input_hash: Some(hash_input(students, categories, fallback)),
+            ..Self::from_bytes(seed)
+        }
+    }
+
+    /// Return the `Lottery` for a published seed
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - Hex encoded seed, as returned by [`seed`](#method.seed)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MatchError::InvalidInput`] when the seed is not a hex encoded 32 byte value.
+    ///
+    /// [`MatchError::InvalidInput`]: enum.MatchError.html#variant.InvalidInput
+    pub fn from_seed(seed: &str) -> Result<Self, MatchError> {
+        let invalid_seed = || MatchError::InvalidInput(vec![Violation::InvalidSeed(seed.into())]);
+
+        if seed.len() != 64 || !seed.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid_seed());
+        }
+
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&seed[i * 2..i * 2 + 2], 16).map_err(|_| invalid_seed())?;
+        }
+
+        Ok(Self::from_bytes(bytes))
+    }
+
+    pub(crate) fn from_bytes(seed: [u8; 32]) -> Self {
+        Lottery {
+            seed,
+            input_hash: None,
+            rng: ChaCha20Rng::from_seed(seed),
+        }
+    }
+
+    /// Return the `Lottery` for a published seed and check that the input did not change
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - Hex encoded seed, as returned by [`seed`](#method.seed)
+    /// * `input_hash` - Hex encoded input hash, as returned by [`input_hash`](#method.input_hash)
+    /// * `students` - Students to match
+    /// * `categories` - Categories to match the students to
+    /// * `fallback` - Policy for students that can't be placed in their preferences
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MatchError::InvalidInput`] when the seed is not a hex encoded 32 byte value or
+    /// when the input doesn't match the input hash.
+    ///
+    /// [`MatchError::InvalidInput`]: enum.MatchError.html#variant.InvalidInput
+    pub fn replay(
+        seed: &str,
+        input_hash: &str,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+    ) -> Result<Self, MatchError> {
+        let lottery = Self::from_seed(seed)?;
+
+        let actual = hash_input(students, categories, fallback);
+        if to_hex(&actual) != input_hash.to_ascii_lowercase() {
+            return Err(MatchError::InvalidInput(vec![Violation::ChangedInput(
+                input_hash.into(),
+            )]));
+        }
+
+        Ok(Lottery {
+            input_hash: Some(actual),
+            ..lottery
+        })
+    }
+
+    /// Return the hex encoded seed of the lottery
+    pub fn seed(&self) -> String {
+        to_hex(&self.seed)
+    }
+
+    /// Return the hex encoded hash of the input, unless the lottery was created with
+    /// [`from_seed`](#method.from_seed)
+    pub fn input_hash(&self) -> Option<String> {
+        self.input_hash.as_ref().map(to_hex)
+    }
+
+    /// Match students with this lottery and record its seed and input hash in the result
+    ///
+    /// # Arguments
+    ///
+    /// * `students` - Students to match
+    /// * `categories` - Categories to match the students to
+    /// * `match_students` - Matching function, like [`da_stb::match_students`]
+    ///
+    /// [`da_stb::match_students`]: da_stb/fn.match_students.html
+    pub fn draw<F>(
+        mut self,
+        students: Vec<Student>,
+        categories: &[Category],
+        match_students: F,
+    ) -> Result<MatchResult, MatchError>
+    where
+        F: FnOnce(Vec<Student>, &[Category], &mut Self) -> Result<MatchResult, MatchError>,
+    {
+        let seed = self.seed();
+        let input_hash = self.input_hash();
+        let mut match_result = match_students(students, categories, &mut self)?;
+        match_result.seed = Some(seed);
+        match_result.input_hash = input_hash;
+        Ok(match_result)
+    }
+}
+
+impl RngCore for Lottery {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Hash the input of a match, as described by [`Lottery::new`](struct.Lottery.html#method.new)
+fn hash_input(students: &[Student], categories: &[Category], fallback: &Fallback) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    update_str(&mut hasher, "matchmaker input v1");
+    update_input(&mut hasher, students, categories, fallback);
+
+    let mut input_hash = [0; 32];
+    input_hash.copy_from_slice(&hasher.finalize());
+    input_hash
+}
+
+/// Write values 3 to 5 of the seed to the hasher
+fn update_input(
+    hasher: &mut Sha256,
+    students: &[Student],
+    categories: &[Category],
+    fallback: &Fallback,
+) {
+    update_usize(hasher, categories.len());
+    for category in categories {
+        update_str(hasher, &category.name);
+        update_usize(hasher, category.max_placements);
+        update_usize(hasher, category.min_placements);
+        update_usize(hasher, category.priorities.len());
+        for (student_name, priority) in category.priorities.iter() {
+            update_str(hasher, student_name);
+            update_usize(hasher, *priority);
+        }
+        update_usize(hasher, category.time_slots.len());
+        for time_slot in category.time_slots.iter() {
+            update_usize(hasher, time_slot.start as usize);
+            update_usize(hasher, time_slot.end as usize);
+        }
+        update_usize(hasher, category.reserves.len());
+        for reserve in category.reserves.iter() {
+            update_str(hasher, &reserve.attribute);
+            update_usize(hasher, reserve.seats);
+        }
+        update_usize(
+            hasher,
+            match category.reserve_order {
+                ReserveOrder::ReservedFirst => 0,
+                ReserveOrder::OpenFirst => 1,
+            },
+        );
+        update_usize(hasher, category.fixed_placements.len());
+        for student_name in category.fixed_placements.iter() {
+            update_str(hasher, student_name);
+        }
+    }
+
+    update_usize(hasher, students.len());
+    for student in students {
+        update_str(hasher, &student.name);
+        update_usize(hasher, student.preferences.len());
+        for category in student.preferences.iter() {
+            update_str(hasher, &category.name);
+        }
+        update_usize(hasher, student.exclude.len());
+        for category in student.exclude.iter() {
+            update_str(hasher, &category.name);
+        }
+        update_usize(hasher, student.indifference_groups.len());
+        for size in student.indifference_groups.iter() {
+            update_usize(hasher, *size);
+        }
+        match &student.group {
+            Some(group) => {
+                update_usize(hasher, 1);
+                update_str(hasher, &group.name);
+                update_usize(hasher, group.hard as usize);
+            }
+            None => update_usize(hasher, 0),
+        }
+        match student.max_categories {
+            Some(max_categories) => {
+                update_usize(hasher, 1);
+                update_usize(hasher, max_categories);
+            }
+            None => update_usize(hasher, 0),
+        }
+        update_usize(hasher, student.attributes.len());
+        for attribute in student.attributes.iter() {
+            update_str(hasher, attribute);
+        }
+        update_usize(hasher, student.consent as usize);
+    }
+
+    match fallback {
+        Fallback::None => update_usize(hasher, 0),
+        Fallback::Random => update_usize(hasher, 1),
+        Fallback::LowestCost(costs) => {
+            update_usize(hasher, 2);
+            update_usize(hasher, costs.len());
+            for (student_name, student_costs) in costs.iter() {
+                update_str(hasher, student_name);
+                update_usize(hasher, student_costs.len());
+                for (category_name, cost) in student_costs.iter() {
+                    update_str(hasher, category_name);
+                    update_usize(hasher, *cost as usize);
+                }
+            }
+        }
+        Fallback::SecondRound(preferences) => {
+            update_usize(hasher, 3);
+            update_usize(hasher, preferences.len());
+            for (student_name, category_names) in preferences.iter() {
+                update_str(hasher, student_name);
+                update_usize(hasher, category_names.len());
+                for category_name in category_names.iter() {
+                    update_str(hasher, category_name);
+                }
+            }
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8; 32]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn update_usize(hasher: &mut Sha256, value: usize) {
+    hasher.update((value as u64).to_le_bytes());
+}
+
+fn update_str(hasher: &mut Sha256, value: &str) {
+    update_usize(hasher, value.len());
+    hasher.update(value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Group;
+    use std::collections::{BTreeMap, VecDeque};
+
+    fn get_data() -> (Vec<Student>, Vec<Category>) {
+        let cooking = Category::new("Cooking", 1).with_priority("Kate", 1);
+        let reading = Category::new("Reading", 2);
+
+        let bert = Student::new(
+            "Bert",
+            VecDeque::from(vec![cooking.clone()]),
+            vec![reading.clone()],
+        );
+        let kate = Student::new("Kate", VecDeque::from(vec![reading.clone()]), Vec::new());
+
+        (vec![bert, kate], vec![cooking, reading])
+    }
+
+    #[test]
+    fn test_new() {
+        let (students, categories) = get_data();
+
+        let seed = Lottery::new("42", &students, &categories).seed();
+
+        assert_eq!(seed.len(), 64);
+        assert_eq!(seed, Lottery::new("42", &students, &categories).seed());
+        assert_ne!(seed, Lottery::new("43", &students, &categories).seed());
+        assert_ne!(seed, Lottery::new("42", &students[..1], &categories).seed());
+
+        // Priorities are part of the input
+        let mut other_categories = categories.clone();
+        other_categories[0] = other_categories[0].clone().with_priority("Bert", 2);
+        assert_ne!(
+            seed,
+            Lottery::new("42", &students, &other_categories).seed()
+        );
+
+        // So are groups
+        let mut other_students = students.clone();
+        other_students[0] = other_students[0].clone().with_group(Group::soft("Friends"));
+        assert_ne!(
+            seed,
+            Lottery::new("42", &other_students, &categories).seed()
+        );
+
+        // As is consent to waive priority
+        let mut other_students = students.clone();
+        other_students[0] = other_students[0].clone().with_consent();
+        assert_ne!(
+            seed,
+            Lottery::new("42", &other_students, &categories).seed()
+        );
+
+        // And the costs of the fallback policy
+        let mut costs = BTreeMap::new();
+        costs.insert("Bert".to_string(), BTreeMap::new());
+        let fallback = Fallback::LowestCost(costs.clone());
+        let with_costs = Lottery::with_fallback("42", &students, &categories, &fallback).seed();
+        assert_ne!(seed, with_costs);
+        costs
+            .get_mut("Bert")
+            .unwrap()
+            .insert("Reading".to_string(), 1);
+        let fallback = Fallback::LowestCost(costs);
+        assert_ne!(
+            with_costs,
+            Lottery::with_fallback("42", &students, &categories, &fallback).seed()
+        );
+        assert_eq!(
+            seed,
+            Lottery::with_fallback("42", &students, &categories, &Fallback::Random).seed()
+        );
+    }
+
+    #[test]
+    fn test_from_seed() {
+        let (students, categories) = get_data();
+        let mut lottery = Lottery::new("42", &students, &categories);
+
+        let mut other_lottery = Lottery::from_seed(&lottery.seed()).unwrap();
+
+        assert_eq!(other_lottery.seed(), lottery.seed());
+        assert_eq!(other_lottery.next_u64(), lottery.next_u64());
+    }
+
+    #[test]
+    fn test_from_seed_invalid() {
+        for seed in &["", "00", &"g".repeat(64), &"+f".repeat(32), &"é".repeat(32)] {
+            assert_eq!(
+                Lottery::from_seed(seed).unwrap_err(),
+                MatchError::InvalidInput(vec![Violation::InvalidSeed(seed.to_string())])
+            );
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        let (students, categories) = get_data();
+        let lottery = Lottery::new("42", &students, &categories);
+        let seed = lottery.seed();
+        let input_hash = lottery.input_hash().unwrap();
+
+        let mut replayed = Lottery::replay(
+            &seed,
+            &input_hash,
+            &students,
+            &categories,
+            &Fallback::Random,
+        )
+        .unwrap();
+
+        assert_eq!(replayed.seed(), seed);
+        assert_eq!(replayed.input_hash(), Some(input_hash.clone()));
+        assert_eq!(
+            replayed.next_u64(),
+            Lottery::from_seed(&seed).unwrap().next_u64()
+        );
+        assert_eq!(Lottery::from_seed(&seed).unwrap().input_hash(), None);
+
+        // The input hash doesn't depend on the public value
+        assert_eq!(
+            input_hash,
+            Lottery::new("43", &students, &categories)
+                .input_hash()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_replay_changed_input() {
+        let (students, categories) = get_data();
+        let lottery = Lottery::new("42", &students, &categories);
+        let input_hash = lottery.input_hash().unwrap();
+
+        assert_eq!(
+            Lottery::replay(
+                &lottery.seed(),
+                &input_hash,
+                &students[..1],
+                &categories,
+                &Fallback::Random
+            )
+            .unwrap_err(),
+            MatchError::InvalidInput(vec![Violation::ChangedInput(input_hash)])
+        );
+    }
+}
@@ -0,0 +1,596 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Command-line interface to match students to categories from CSV or JSON files
+
+use matchmaker::{
+    Category, Fallback, Group, Lottery, MatchError, MatchResult, Matcher, Mechanism,
+    MultipleCategoryOptions, ReserveOrder, Student,
+};
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "Usage: matchmaker [OPTIONS] <CATEGORIES> <STUDENTS>
+
+Match students to categories and write the result as JSON.
+
+Arguments:
+  <CATEGORIES>  CSV or JSON file with the categories
+  <STUDENTS>    CSV or JSON file with the students
+
+Options:
+  -a, --algorithm <ALGORITHM>  da-stb (default), da-mtb, ttc, immediate-acceptance,
+                               eadam or probabilistic-serial
+  -m, --mode <MODE>            single (default) or multiple, to place a student in more
+                               than one category
+      --max-categories <N>     Place a student in at most N categories (mode multiple)
+      --preferences-only       Only place a student in categories from its preferences
+                               after the first round (mode multiple)
+  -f, --fallback <POLICY>      Place students that don't get one of their preferences
+                               random (default), none, lowest-cost or second-round
+  -s, --seed <VALUE>           Seed the lottery with a public value and the input
+      --replay <RESULT>        Reproduce the lottery of an earlier result file, if the
+                               input did not change
+  -o, --output <FILE>          Write the result to a file instead of standard output
+  -h, --help                   Print this message
+
+CSV files need a header row. A categories file has the columns name,
+max_placements and optionally min_placements, priorities, time_slots,
+reserves, reserve_order and fixed_placements. Priorities are written as
+student:priority (like Bert:2), time slots as start-end (like 840-930),
+reserves as attribute:seats (like low-income:3), and fixed placements as
+student names (da-stb only), all separated by |. The reserve_order is
+reserved-first (default) or open-first. A students file has the column name,
+followed by columns starting with preference (in order of preference), columns
+starting with exclude and columns starting with attribute. Empty cells are
+ignored. Separate equally preferred categories in a preference cell with |.
+Students with the same name in the group column are only placed together,
+students with the same name in the soft_group column are placed on their own
+when they can't be placed together (da-stb only). An optional max_categories
+column overrides --max-categories for a student, and a consent column set to
+true lets a student waive priority (eadam only). The costs column holds the
+costs of --fallback lowest-cost as category:cost (like Cooking:1200),
+separated by |, and columns starting with second_round hold the preferences of
+--fallback second-round.
+
+JSON files hold a list of objects with the same fields. Preferences and
+exclude are lists of category names, indifference_groups holds the sizes of
+the groups of equally preferred categories, group holds the name of the group
+and whether it is hard, and a category can hold the priorities per student
+name, a list of time slots with a start and end, a list of reserves with an
+attribute and seats, a reserve_order of ReservedFirst or OpenFirst, and a list
+of fixed_placements with student names. A student can hold costs per category
+name, a second_round list of category names and a consent flag.";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Single,
+    Multiple,
+}
+
+/// Fallback policy, whose costs or preferences are read from the students file
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FallbackPolicy {
+    None,
+    Random,
+    LowestCost,
+    SecondRound,
+}
+
+#[derive(Debug, PartialEq)]
+enum Seed {
+    Public(String),
+    Replay(String),
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    categories: String,
+    students: String,
+    mechanism: Mechanism,
+    mode: Mode,
+    options: MultipleCategoryOptions,
+    fallback: FallbackPolicy,
+    seed: Option<Seed>,
+    output: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StudentRecord {
+    name: String,
+    #[serde(default)]
+    preferences: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    indifference_groups: Vec<usize>,
+    #[serde(default)]
+    group: Option<Group>,
+    #[serde(default)]
+    max_categories: Option<usize>,
+    #[serde(default)]
+    attributes: Vec<String>,
+    #[serde(default)]
+    costs: BTreeMap<String, u32>,
+    #[serde(default)]
+    second_round: Vec<String>,
+    #[serde(default)]
+    consent: bool,
+}
+
+#[derive(Deserialize)]
+struct CategoryRecord {
+    name: String,
+    max_placements: usize,
+    #[serde(default)]
+    min_placements: usize,
+    #[serde(default)]
+    priorities: String,
+    #[serde(default)]
+    time_slots: String,
+    #[serde(default)]
+    reserves: String,
+    #[serde(default)]
+    reserve_order: String,
+    #[serde(default)]
+    fixed_placements: String,
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = run(args) {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+/// Parse the command-line arguments, returning `None` when help is requested
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut args = args.into_iter();
+    let mut files: Vec<String> = Vec::new();
+    let mut mechanism = Mechanism::DaStb;
+    let mut mode = Mode::Single;
+    let mut options = MultipleCategoryOptions::default();
+    let mut fallback = FallbackPolicy::Random;
+    let mut seed = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-a" | "--algorithm" => {
+                mechanism = match value(&arg)?.as_str() {
+                    "da-stb" => Mechanism::DaStb,
+                    "da-mtb" => Mechanism::DaMtb,
+                    "ttc" => Mechanism::Ttc,
+                    "immediate-acceptance" => Mechanism::ImmediateAcceptance,
+                    "eadam" => Mechanism::Eadam,
+                    "probabilistic-serial" => Mechanism::ProbabilisticSerial,
+                    other => return Err(format!("unknown algorithm {}", other)),
+                }
+            }
+            "-m" | "--mode" => {
+                mode = match value(&arg)?.as_str() {
+                    "single" => Mode::Single,
+                    "multiple" => Mode::Multiple,
+                    other => return Err(format!("unknown mode {}", other)),
+                }
+            }
+            "--max-categories" => {
+                let max_categories = value(&arg)?;
+                options.max_categories = Some(
+                    max_categories
+                        .parse()
+                        .map_err(|_| format!("invalid number {}", max_categories))?,
+                );
+            }
+            "--preferences-only" => options.preferences_only = true,
+            "-f" | "--fallback" => {
+                fallback = match value(&arg)?.as_str() {
+                    "none" => FallbackPolicy::None,
+                    "random" => FallbackPolicy::Random,
+                    "lowest-cost" => FallbackPolicy::LowestCost,
+                    "second-round" => FallbackPolicy::SecondRound,
+                    other => return Err(format!("unknown fallback {}", other)),
+                }
+            }
+            "-s" | "--seed" => seed = Some(Seed::Public(value(&arg)?)),
+            "--replay" => seed = Some(Seed::Replay(value(&arg)?)),
+            "-o" | "--output" => output = Some(value(&arg)?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.len() != 2 {
+        return Err("expected a categories file and a students file".into());
+    }
+    if mode == Mode::Single && options != MultipleCategoryOptions::default() {
+        return Err("--max-categories and --preferences-only need mode multiple".into());
+    }
+
+    let students = files.pop().unwrap();
+    let categories = files.pop().unwrap();
+
+    Ok(Some(Args {
+        categories,
+        students,
+        mechanism,
+        mode,
+        options,
+        fallback,
+        seed,
+        output,
+    }))
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let categories = read_categories(&args.categories)?;
+    let (students, fallback) = read_students(&args.students, &categories, args.fallback)?;
+
+    let draw = |students, categories: &[Category], lottery: &mut Lottery| {
+        match_students(&args, &fallback, students, categories, lottery)
+    };
+    let match_result = match &args.seed {
+        Some(Seed::Public(value)) => {
+            Lottery::with_fallback(value, &students, &categories, &fallback).draw(
+                students,
+                &categories,
+                draw,
+            )?
+        }
+        Some(Seed::Replay(path)) => {
+            let earlier: MatchResult = serde_json::from_reader(File::open(path)?)?;
+            let (seed, input_hash) = match (&earlier.seed, &earlier.input_hash) {
+                (Some(seed), Some(input_hash)) => (seed, input_hash),
+                _ => return Err(format!("{} has no seed and input hash", path).into()),
+            };
+            Lottery::replay(seed, input_hash, &students, &categories, &fallback)?.draw(
+                students,
+                &categories,
+                draw,
+            )?
+        }
+        None => match_students(&args, &fallback, students, &categories, &mut thread_rng())?,
+    };
+
+    match &args.output {
+        Some(path) => write_result(File::create(path)?, &match_result),
+        None => write_result(io::stdout().lock(), &match_result),
+    }
+}
+
+fn match_students(
+    args: &Args,
+    fallback: &Fallback,
+    students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    match args.mode {
+        Mode::Single => args
+            .mechanism
+            .match_students_with_fallback(students, categories, fallback, rng),
+        Mode::Multiple => args.mechanism.match_students_to_multiple_categories(
+            students,
+            categories,
+            &MultipleCategoryOptions {
+                fallback: fallback.clone(),
+                ..args.options.clone()
+            },
+            rng,
+        ),
+    }
+}
+
+fn write_result(mut writer: impl Write, match_result: &MatchResult) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer_pretty(&mut writer, match_result)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+fn is_csv(path: &str) -> Result<bool, String> {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("csv") => Ok(true),
+        Some(extension) if extension.eq_ignore_ascii_case("json") => Ok(false),
+        _ => Err(format!("{} is not a .csv or .json file", path)),
+    }
+}
+
+fn read_categories(path: &str) -> Result<Vec<Category>, Box<dyn Error>> {
+    if !is_csv(path)? {
+        return Ok(serde_json::from_reader(File::open(path)?)?);
+    }
+
+    let mut categories = Vec::new();
+    for record in csv::Reader::from_path(path)?.deserialize() {
+        let record: CategoryRecord = record?;
+        let mut category = Category::new(&record.name, record.max_placements)
+            .with_min_placements(record.min_placements);
+
+        for priority in record.priorities.split('|').map(|p| p.trim()) {
+            if priority.is_empty() {
+                continue;
+            }
+            let invalid = || format!("invalid priority {} for {}", priority, record.name);
+            let (student_name, priority) = priority.rsplit_once(':').ok_or_else(invalid)?;
+            category = category.with_priority(
+                student_name.trim(),
+                priority.trim().parse().map_err(|_| invalid())?,
+            );
+        }
+
+        for time_slot in record.time_slots.split('|').map(|t| t.trim()) {
+            if time_slot.is_empty() {
+                continue;
+            }
+            let invalid = || format!("invalid time slot {} for {}", time_slot, record.name);
+            let (start, end) = time_slot.split_once('-').ok_or_else(invalid)?;
+            category = category.with_time_slot(
+                start.trim().parse().map_err(|_| invalid())?,
+                end.trim().parse().map_err(|_| invalid())?,
+            );
+        }
+
+        for reserve in record.reserves.split('|').map(|r| r.trim()) {
+            if reserve.is_empty() {
+                continue;
+            }
+            let invalid = || format!("invalid reserve {} for {}", reserve, record.name);
+            let (attribute, seats) = reserve.rsplit_once(':').ok_or_else(invalid)?;
+            category = category.with_reserve(
+                attribute.trim(),
+                seats.trim().parse().map_err(|_| invalid())?,
+            );
+        }
+
+        category = category.with_reserve_order(match record.reserve_order.trim() {
+            "" | "reserved-first" => ReserveOrder::ReservedFirst,
+            "open-first" => ReserveOrder::OpenFirst,
+            other => {
+                return Err(format!("invalid reserve_order {} for {}", other, record.name).into())
+            }
+        });
+
+        for student_name in record.fixed_placements.split('|').map(|n| n.trim()) {
+            if !student_name.is_empty() {
+                category = category.with_fixed_placement(student_name);
+            }
+        }
+        categories.push(category);
+    }
+    Ok(categories)
+}
+
+/// Read the students, and the costs or preferences of the fallback policy
+fn read_students(
+    path: &str,
+    categories: &[Category],
+    policy: FallbackPolicy,
+) -> Result<(Vec<Student>, Fallback), Box<dyn Error>> {
+    let records: Vec<StudentRecord> = if is_csv(path)? {
+        let mut reader = csv::Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let mut records = Vec::new();
+
+        for row in reader.records() {
+            let row = row?;
+            let mut record = StudentRecord {
+                name: String::new(),
+                preferences: Vec::new(),
+                exclude: Vec::new(),
+                indifference_groups: Vec::new(),
+                group: None,
+                max_categories: None,
+                attributes: Vec::new(),
+                costs: BTreeMap::new(),
+                second_round: Vec::new(),
+                consent: false,
+            };
+            for (header, cell) in headers.iter().zip(row.iter()) {
+                let cell = cell.trim();
+                if header == "name" {
+                    record.name = cell.into();
+                } else if cell.is_empty() {
+                    continue;
+                } else if header.starts_with("preference") {
+                    // Equally preferred categories share a cell.
+                    let group: Vec<&str> = cell.split('|').map(|c| c.trim()).collect();
+                    record.indifference_groups.push(group.len());
+                    record
+                        .preferences
+                        .extend(group.into_iter().map(String::from));
+                } else if header.starts_with("exclude") {
+                    record.exclude.push(cell.into());
+                } else if header.starts_with("attribute") {
+                    record.attributes.push(cell.into());
+                } else if header.starts_with("second_round") {
+                    record.second_round.push(cell.into());
+                } else if header == "costs" {
+                    for cost in cell.split('|').map(|c| c.trim()) {
+                        let invalid = || format!("invalid cost {} for {}", cost, record.name);
+                        let (category, cost) = cost.rsplit_once(':').ok_or_else(invalid)?;
+                        record.costs.insert(
+                            category.trim().into(),
+                            cost.trim().parse().map_err(|_| invalid())?,
+                        );
+                    }
+                } else if header == "group" {
+                    record.group = Some(Group::hard(cell));
+                } else if header == "soft_group" {
+                    record.group = Some(Group::soft(cell));
+                } else if header == "consent" {
+                    record.consent = cell
+                        .parse()
+                        .map_err(|_| format!("invalid consent {} for {}", cell, record.name))?;
+                } else if header == "max_categories" {
+                    record.max_categories = Some(cell.parse().map_err(|_| {
+                        format!("invalid max_categories {} for {}", cell, record.name)
+                    })?);
+                }
+            }
+            if record.indifference_groups.iter().all(|&size| size == 1) {
+                record.indifference_groups.clear();
+            }
+            records.push(record);
+        }
+        records
+    } else {
+        serde_json::from_reader(File::open(path)?)?
+    };
+
+    // Unknown category names are reported when the students are validated.
+    let find_category = |name: &String| {
+        categories
+            .iter()
+            .find(|c| &c.name == name)
+            .cloned()
+            .unwrap_or_else(|| Category::new(name, 0))
+    };
+
+    let fallback = match policy {
+        FallbackPolicy::None => Fallback::None,
+        FallbackPolicy::Random => Fallback::Random,
+        FallbackPolicy::LowestCost => Fallback::LowestCost(
+            records
+                .iter()
+                .map(|r| (r.name.clone(), r.costs.clone()))
+                .collect(),
+        ),
+        FallbackPolicy::SecondRound => Fallback::SecondRound(
+            records
+                .iter()
+                .map(|r| (r.name.clone(), r.second_round.clone()))
+                .collect(),
+        ),
+    };
+
+    let students = records
+        .into_iter()
+        .map(|record| {
+            let mut student = Student::new(
+                &record.name,
+                record
+                    .preferences
+                    .iter()
+                    .map(find_category)
+                    .collect::<VecDeque<_>>(),
+                record.exclude.iter().map(find_category).collect(),
+            );
+            student.indifference_groups = record.indifference_groups;
+            student.group = record.group;
+            student.max_categories = record.max_categories;
+            student.attributes = record.attributes.into_iter().collect();
+            student.consent = record.consent;
+            student
+        })
+        .collect();
+
+    Ok((students, fallback))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&["categories.csv", "students.csv"]),
+            Ok(Some(Args {
+                categories: "categories.csv".into(),
+                students: "students.csv".into(),
+                mechanism: Mechanism::DaStb,
+                mode: Mode::Single,
+                options: MultipleCategoryOptions::default(),
+                fallback: FallbackPolicy::Random,
+                seed: None,
+                output: None,
+            }))
+        );
+        assert_eq!(
+            parse(&[
+                "-a",
+                "da-mtb",
+                "--mode",
+                "multiple",
+                "categories.json",
+                "--seed",
+                "42",
+                "--max-categories",
+                "2",
+                "--preferences-only",
+                "-f",
+                "second-round",
+                "students.json",
+                "-o",
+                "result.json",
+            ]),
+            Ok(Some(Args {
+                categories: "categories.json".into(),
+                students: "students.json".into(),
+                mechanism: Mechanism::DaMtb,
+                mode: Mode::Multiple,
+                options: MultipleCategoryOptions {
+                    max_categories: Some(2),
+                    preferences_only: true,
+                    fallback: Fallback::Random,
+                },
+                fallback: FallbackPolicy::SecondRound,
+                seed: Some(Seed::Public("42".into())),
+                output: Some("result.json".into()),
+            }))
+        );
+        assert_eq!(
+            parse(&[
+                "-a",
+                "ttc",
+                "-m",
+                "multiple",
+                "categories.csv",
+                "students.csv"
+            ])
+            .unwrap()
+            .unwrap()
+            .mechanism,
+            Mechanism::Ttc
+        );
+        assert_eq!(parse(&["categories.csv", "--help"]), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_args_invalid() {
+        assert!(parse(&["categories.csv"]).is_err());
+        assert!(parse(&["categories.csv", "students.csv", "--seed"]).is_err());
+        assert!(parse(&["-a", "random", "categories.csv", "students.csv"]).is_err());
+        assert!(parse(&["--max-categories", "two", "categories.csv", "students.csv"]).is_err());
+        assert!(parse(&["--preferences-only", "categories.csv", "students.csv"]).is_err());
+        assert!(parse(&["--fallback", "nearest", "categories.csv", "students.csv"]).is_err());
+    }
+}
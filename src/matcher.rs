@@ -0,0 +1,274 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Defines the interface every matching mechanism implements
+
+use super::da_mtb::DaMtb;
+use super::da_stb::{match_multiple_categories, match_with_minimums, DaStb};
+use super::eadam::Eadam;
+use super::immediate_acceptance::ImmediateAcceptance;
+use super::probabilistic_serial::ProbabilisticSerial;
+use super::ttc::Ttc;
+use super::validation::validate_fallback;
+use super::{
+    validate, Category, Fallback, MatchError, MatchResult, MultipleCategoryOptions, Student,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+/// Mechanism that matches students to categories
+///
+/// Every module of a mechanism has a matcher, like [`DaStb`](da_stb/struct.DaStb.html), to
+/// choose the mechanism at runtime. The functions of the module call its matcher.
+///
+/// A new mechanism only implements [`match_unchecked`](#tymethod.match_unchecked), and
+/// [`validate`](#method.validate) when it doesn't support all input. Validation, minimum
+/// placements and matching to multiple categories are provided.
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::da_stb::DaStb;
+/// use matchmaker::ttc::Ttc;
+/// use matchmaker::{Category, Matcher, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new());
+///
+/// let students = Vec::from([bert]);
+/// let categories = Vec::from([cooking]);
+///
+/// let mut rng = thread_rng();
+/// let matchers: [&dyn Matcher; 2] = [&DaStb, &Ttc];
+/// for matcher in matchers.iter() {
+///     let match_result = matcher
+///         .match_students(students.clone(), &categories, &mut rng)
+///         .unwrap();
+///     assert_eq!(match_result.placed["Cooking"].len(), 1);
+/// }
+/// ```
+pub trait Matcher {
+    /// Match students to categories, so each student is placed in at most one category
+    ///
+    /// The students and categories are valid, see [`validate`](#method.validate), and
+    /// categories without enough students are cancelled by the caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `students` - Students to match
+    /// * `categories` - Categories to match the students to
+    /// * `fallback` - Policy for students that can't be placed in their preferences
+    /// * `rng` - Random number generator used for the lottery
+    fn match_unchecked(
+        &self,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+        rng: &mut dyn RngCore,
+    ) -> MatchResult;
+
+    /// Check students and categories for inconsistencies and for input the mechanism doesn't
+    /// support
+    ///
+    /// Calls [`validate`](../fn.validate.html) by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `students` - Students to match
+    /// * `categories` - Categories to match the students to
+    fn validate(&self, students: &[Student], categories: &[Category]) -> Result<(), MatchError> {
+        validate(students, categories)
+    }
+
+    /// Match students to categories
+    ///
+    /// Use this method if each student can only be placed in one category. Students that
+    /// can't be placed in their preferences are placed in a random open category.
+    ///
+    /// # Arguments
+    ///
+    /// * `students` - Students to match
+    /// * `categories` - Categories to match the students to
+    /// * `rng` - Random number generator used for the lottery
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MatchError::InvalidInput`] when the input is not valid, see
+    /// [`validate`](#method.validate).
+    ///
+    /// [`MatchError::InvalidInput`]: enum.MatchError.html#variant.InvalidInput
+    fn match_students(
+        &self,
+        students: Vec<Student>,
+        categories: &[Category],
+        rng: &mut dyn RngCore,
+    ) -> Result<MatchResult, MatchError> {
+        self.match_students_with_fallback(students, categories, &Fallback::default(), rng)
+    }
+
+    /// Match students to categories, placing students that can't be placed in their
+    /// preferences by a fallback policy
+    ///
+    /// The policy is recorded in the result.
+    ///
+    /// # Arguments
+    ///
+    /// * `students` - Students to match
+    /// * `categories` - Categories to match the students to
+    /// * `fallback` - Policy for students that can't be placed in their preferences
+    /// * `rng` - Random number generator used for the lottery
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MatchError::InvalidInput`] when the input is not valid, see
+    /// [`validate`](#method.validate), or when the policy refers to students or categories
+    /// that are not part of the match.
+    ///
+    /// [`MatchError::InvalidInput`]: enum.MatchError.html#variant.InvalidInput
+    fn match_students_with_fallback(
+        &self,
+        mut students: Vec<Student>,
+        categories: &[Category],
+        fallback: &Fallback,
+        rng: &mut dyn RngCore,
+    ) -> Result<MatchResult, MatchError> {
+        self.validate(&students, categories)?;
+        validate_fallback(fallback, &students, categories)?;
+        students.iter_mut().for_each(Student::clear_priorities);
+        let mut match_result =
+            match_with_minimums(students, categories, rng, |students, categories, rng| {
+                self.match_unchecked(students, categories, fallback, rng)
+            });
+        match_result.fallback = fallback.clone();
+        Ok(match_result)
+    }
+
+    /// Match students to more than one category
+    ///
+    /// Runs the mechanism until no more spots are filled, so a single student can be placed
+    /// in more than one category. Students are never placed in two categories with
+    /// overlapping time slots.
+    ///
+    /// # Arguments
+    ///
+    /// * `students` - Students to match
+    /// * `categories` - Categories to match the students to
+    /// * `options` - Limits on the categories a student can be placed in, and the fallback
+    ///   policy
+    /// * `rng` - Random number generator used for the lottery
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MatchError::InvalidInput`] when the input is not valid, see
+    /// [`validate`](#method.validate), or when the fallback policy refers to students or
+    /// categories that are not part of the match.
+    ///
+    /// [`MatchError::InvalidInput`]: enum.MatchError.html#variant.InvalidInput
+    fn match_students_to_multiple_categories(
+        &self,
+        mut students: Vec<Student>,
+        categories: &[Category],
+        options: &MultipleCategoryOptions,
+        rng: &mut dyn RngCore,
+    ) -> Result<MatchResult, MatchError> {
+        self.validate(&students, categories)?;
+        validate_fallback(&options.fallback, &students, categories)?;
+        students.iter_mut().for_each(Student::clear_priorities);
+        let mut match_result = match_with_minimums(
+            students,
+            categories,
+            rng,
+            |students, categories, mut rng| {
+                match_multiple_categories(
+                    students,
+                    categories,
+                    options,
+                    &mut rng,
+                    |students, categories, rng| {
+                        self.match_unchecked(students, categories, &options.fallback, &mut **rng)
+                    },
+                )
+            },
+        );
+        match_result.fallback = options.fallback.clone();
+        Ok(match_result)
+    }
+}
+
+/// Names every mechanism of this library, so it can be chosen by a configuration value
+///
+/// Serializes as `da-stb`, `da-mtb`, `ttc`, `immediate-acceptance`, `eadam` or
+/// `probabilistic-serial`.
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::{Category, Matcher, Mechanism, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// // Read from a configuration file as "immediate-acceptance"
+/// let mechanism = Mechanism::ImmediateAcceptance;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new());
+///
+/// let match_result = mechanism
+///     .match_students(Vec::from([bert]), &[cooking], &mut thread_rng())
+///     .unwrap();
+/// assert_eq!(match_result.placed["Cooking"].len(), 1);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mechanism {
+    /// Deferred Acceptance - Single Tie Break, see [`DaStb`](da_stb/struct.DaStb.html)
+    DaStb,
+    /// Deferred Acceptance - Multiple Tie Break, see [`DaMtb`](da_mtb/struct.DaMtb.html)
+    DaMtb,
+    /// Top Trading Cycles, see [`Ttc`](ttc/struct.Ttc.html)
+    Ttc,
+    /// Immediate Acceptance, see
+    /// [`ImmediateAcceptance`](immediate_acceptance/struct.ImmediateAcceptance.html)
+    ImmediateAcceptance,
+    /// Efficiency-Adjusted Deferred Acceptance, see [`Eadam`](eadam/struct.Eadam.html)
+    Eadam,
+    /// Probabilistic Serial, see
+    /// [`ProbabilisticSerial`](probabilistic_serial/struct.ProbabilisticSerial.html)
+    ProbabilisticSerial,
+}
+
+impl Mechanism {
+    /// Return the matcher that implements the mechanism
+    pub fn matcher(self) -> &'static dyn Matcher {
+        match self {
+            Mechanism::DaStb => &DaStb,
+            Mechanism::DaMtb => &DaMtb,
+            Mechanism::Ttc => &Ttc,
+            Mechanism::ImmediateAcceptance => &ImmediateAcceptance,
+            Mechanism::Eadam => &Eadam,
+            Mechanism::ProbabilisticSerial => &ProbabilisticSerial,
+        }
+    }
+}
+
+impl Matcher for Mechanism {
+    fn match_unchecked(
+        &self,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+        rng: &mut dyn RngCore,
+    ) -> MatchResult {
+        self.matcher()
+            .match_unchecked(students, categories, fallback, rng)
+    }
+
+    fn validate(&self, students: &[Student], categories: &[Category]) -> Result<(), MatchError> {
+        self.matcher().validate(students, categories)
+    }
+}
@@ -0,0 +1,644 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Implements the Probabilistic Serial (or simultaneous eating) algorithm of Bogomolnaia and
+//! Moulin
+//!
+//! Every student eats from its most preferred category that is left, all at the same speed,
+//! until each student ate one placement or nothing it prefers is left. The share a student
+//! ate of a category is the probability that it is placed there, see
+//! [`random_assignment`](fn.random_assignment.html). No student prefers the probabilities of
+//! another student to its own, which a random order of students like
+//! [`da_stb`](../da_stb/index.html) without priorities doesn't guarantee.
+//!
+//! A match is drawn from these probabilities by splitting them into matches with a weight
+//! each, a Birkhoff-von Neumann decomposition, and drawing one of those matches by its
+//! weight. Priorities of the categories are not used, equally preferred categories are eaten
+//! in the order they are listed, and the lottery numbers of the result only decide the order
+//! of the fallback policy.
+
+use super::da_stb::{assign_fallback, draw_order};
+use super::validation::{reject_fixed_placements, reject_groups, reject_reserves};
+use super::{
+    category_indices, validate, Category, Fallback, MatchError, MatchResult, Matcher,
+    OrderedStudent, Student, StudentsPerCategory, Violation,
+};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+/// Shares below this are left out, to keep rounding errors from adding placements
+const EPSILON: f64 = 1e-9;
+
+/// Probabilistic Serial mechanism
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProbabilisticSerial;
+
+impl Matcher for ProbabilisticSerial {
+    fn match_unchecked(
+        &self,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+        mut rng: &mut dyn RngCore,
+    ) -> MatchResult {
+        let shares = eat(&preference_indices(students, categories), categories);
+        let (mut placed, not_placable) = draw_placements(students, categories, &shares, &mut rng);
+
+        // Assign unplaced students to open spots by the fallback policy.
+        let not_placable =
+            assign_fallback(not_placable, &mut placed, categories, fallback, &mut rng);
+
+        let waitlists: StudentsPerCategory = vec![Vec::new(); categories.len()];
+        MatchResult::from(placed, not_placable, waitlists, categories)
+    }
+
+    fn validate(&self, students: &[Student], categories: &[Category]) -> Result<(), MatchError> {
+        validate(students, categories)?;
+        reject_groups(students)?;
+        reject_reserves(categories)?;
+        reject_fixed_placements(categories)?;
+        Ok(())
+    }
+}
+
+/// Holds the probability of every placement of every student, see
+/// [`random_assignment`](fn.random_assignment.html)
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RandomAssignment {
+    /// Probability that the student is placed in the category, per student name and category
+    /// name
+    pub placement_probabilities: BTreeMap<String, BTreeMap<String, f64>>,
+    /// Probability that the student is not placed in any of its preferences, per student name
+    pub not_placable_probabilities: BTreeMap<String, f64>,
+}
+
+/// Match students to categories
+///
+/// Use this function if each student can only be placed in one category
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::probabilistic_serial::match_students;
+/// use matchmaker::{Category, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let reading = Category::new("Reading", 1);
+///
+/// // Bert and Suze both wish to be placed in category cooking, then reading
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// let mut rng = thread_rng();
+/// let categories = Vec::from([cooking, reading]);
+///
+/// let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng).unwrap();
+///
+/// assert_eq!(match_result.placed.get("Cooking").unwrap().len(), 1);
+/// assert_eq!(match_result.placed.get("Reading").unwrap().len(), 1);
+/// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], or when students are part of a [`Group`] or categories have reserved
+/// seats or fixed placements, see [`Violation`].
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+/// [`Group`]: ../struct.Group.html
+/// [`Violation`]: ../enum.Violation.html
+pub fn match_students(
+    students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    ProbabilisticSerial.match_students(students, categories, rng)
+}
+
+/// Return the probability of every placement of every student
+///
+/// The minimum number of students of the categories is not taken into account.
+///
+/// # Arguments
+///
+/// * `students` - Students to match
+/// * `categories` - Categories to match the students to
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the input is not valid, see
+/// [`match_students`](fn.match_students.html).
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::probabilistic_serial::random_assignment;
+/// use matchmaker::{Category, Student};
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let reading = Category::new("Reading", 1);
+///
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// let categories = Vec::from([cooking, reading]);
+/// let assignment = random_assignment(&[bert, suze], &categories).unwrap();
+///
+/// // Bert and Suze share both categories
+/// assert_eq!(assignment.placement_probabilities["Bert"]["Cooking"], 0.5);
+/// assert_eq!(assignment.placement_probabilities["Suze"]["Reading"], 0.5);
+/// assert_eq!(assignment.not_placable_probabilities["Bert"], 0.0);
+/// ```
+pub fn random_assignment(
+    students: &[Student],
+    categories: &[Category],
+) -> Result<RandomAssignment, MatchError> {
+    ProbabilisticSerial.validate(students, categories)?;
+
+    let shares = eat(&preference_indices(students, categories), categories);
+
+    let mut placement_probabilities = BTreeMap::new();
+    let mut not_placable_probabilities = BTreeMap::new();
+    for (student, shares) in students.iter().zip(shares) {
+        let mut probabilities: BTreeMap<String, f64> =
+            categories.iter().map(|c| (c.name.clone(), 0.0)).collect();
+        for &(category, share) in shares.iter() {
+            probabilities.insert(categories[category].name.clone(), share);
+        }
+        let placed: f64 = shares.iter().map(|&(_, share)| share).sum();
+
+        placement_probabilities.insert(student.name.clone(), probabilities);
+        not_placable_probabilities.insert(student.name.clone(), (1.0 - placed).max(0.0));
+    }
+
+    Ok(RandomAssignment {
+        placement_probabilities,
+        not_placable_probabilities,
+    })
+}
+
+/// Draw a match from the probabilities of a random assignment
+///
+/// The probabilities are split into matches with a weight each, and one of them is drawn by
+/// its weight, so every student is placed in a category with the probability of the
+/// assignment. Students that are not placed stay in `not_placable`, the fallback policy
+/// of the result is [`Fallback::None`]. `not_placable_probabilities` is not used.
+///
+/// # Arguments
+///
+/// * `students` - Students to match
+/// * `categories` - Categories to match the students to
+/// * `assignment` - Probabilities to draw from, like the result of
+///   [`random_assignment`](fn.random_assignment.html)
+/// * `rng` - Random number generator used for the draw
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the input is not valid, see
+/// [`match_students`](fn.match_students.html), when the assignment refers to students or
+/// categories that are not part of the match, or when the probabilities are negative or add
+/// up to more than one placement per student or the capacity of a category.
+///
+/// [`Fallback::None`]: ../enum.Fallback.html#variant.None
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::probabilistic_serial::{random_assignment, sample};
+/// use matchmaker::{Category, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 1);
+/// let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new());
+/// let suze = Student::new("Suze", VecDeque::from(vec![cooking.clone()]), Vec::new());
+///
+/// let students = Vec::from([bert, suze]);
+/// let categories = Vec::from([cooking]);
+///
+/// let assignment = random_assignment(&students, &categories).unwrap();
+/// let match_result = sample(students, &categories, &assignment, &mut thread_rng()).unwrap();
+///
+/// // Bert or Suze is placed, each half of the time
+/// assert_eq!(match_result.placed["Cooking"].len(), 1);
+/// assert_eq!(match_result.not_placable.len(), 1);
+/// ```
+pub fn sample(
+    students: Vec<Student>,
+    categories: &[Category],
+    assignment: &RandomAssignment,
+    mut rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    ProbabilisticSerial.validate(&students, categories)?;
+    validate_probabilities(assignment, &students, categories)?;
+
+    let indices = category_indices(categories);
+    let shares: Vec<Vec<(usize, f64)>> = students
+        .iter()
+        .map(|s| {
+            assignment
+                .placement_probabilities
+                .get(&s.name)
+                .map(|probabilities| {
+                    probabilities
+                        .iter()
+                        .filter(|&(_, &p)| p > EPSILON)
+                        .map(|(c, &p)| (indices[c.as_str()], p))
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let (placed, not_placable) = draw_placements(&students, categories, &shares, &mut rng);
+
+    let waitlists: StudentsPerCategory = vec![Vec::new(); categories.len()];
+    let mut match_result = MatchResult::from(placed, not_placable, waitlists, categories);
+    match_result.fallback = Fallback::None;
+    Ok(match_result)
+}
+
+/// Report students and categories of a random assignment that are not part of the match, and
+/// probabilities that no draw of matches can give
+fn validate_probabilities(
+    assignment: &RandomAssignment,
+    students: &[Student],
+    categories: &[Category],
+) -> Result<(), MatchError> {
+    let mut violations: Vec<Violation> = Vec::new();
+    let mut totals: Vec<f64> = vec![0.0; categories.len()];
+
+    for (student, probabilities) in assignment.placement_probabilities.iter() {
+        if !students.iter().any(|s| &s.name == student) {
+            violations.push(Violation::UnknownStudent(student.clone()));
+        }
+        for (name, &probability) in probabilities.iter() {
+            match categories.iter().position(|c| &c.name == name) {
+                Some(i) => totals[i] += probability,
+                None => violations.push(Violation::UnknownCategory {
+                    student: student.clone(),
+                    category: name.clone(),
+                }),
+            }
+        }
+        if probabilities.values().any(|&p| p < 0.0)
+            || probabilities.values().sum::<f64>() > 1.0 + EPSILON
+        {
+            violations.push(Violation::InvalidProbabilities(student.clone()));
+        }
+    }
+    for (category, total) in categories.iter().zip(totals) {
+        if total > category.max_placements as f64 + EPSILON {
+            violations.push(Violation::InvalidProbabilities(category.name.clone()));
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(MatchError::InvalidInput(violations))
+    }
+}
+
+/// Return the indices of the preferences of every student, in the order they are listed
+fn preference_indices(students: &[Student], categories: &[Category]) -> Vec<Vec<usize>> {
+    let indices = category_indices(categories);
+    students
+        .iter()
+        .map(|s| {
+            s.preferences
+                .iter()
+                .filter_map(|c| indices.get(c.name.as_str()).copied())
+                .collect()
+        })
+        .collect()
+}
+
+/// Let every student eat from its preferences at the same speed, until every student ate one
+/// placement or nothing it prefers is left
+///
+/// Returns the share of every category index that every student ate, in order of eating.
+fn eat(preferences: &[Vec<usize>], categories: &[Category]) -> Vec<Vec<(usize, f64)>> {
+    let mut left: Vec<f64> = categories.iter().map(|c| c.max_placements as f64).collect();
+    let mut shares: Vec<Vec<(usize, f64)>> = vec![Vec::new(); preferences.len()];
+    let mut cursors: Vec<usize> = vec![0; preferences.len()];
+    let mut time = 0.0;
+
+    while time < 1.0 {
+        // Every student eats from its most preferred category that is left.
+        let mut eaters: Vec<usize> = vec![0; categories.len()];
+        for (preferences, cursor) in preferences.iter().zip(cursors.iter_mut()) {
+            while *cursor < preferences.len() && left[preferences[*cursor]] <= 0.0 {
+                *cursor += 1;
+            }
+            if let Some(&category) = preferences.get(*cursor) {
+                eaters[category] += 1;
+            }
+        }
+
+        // Eat until the first category runs out, or the time is up.
+        let duration = eaters
+            .iter()
+            .zip(left.iter())
+            .filter(|(&eaters, _)| eaters > 0)
+            .map(|(&eaters, &left)| left / eaters as f64)
+            .fold(1.0 - time, f64::min);
+        if eaters.iter().all(|&eaters| eaters == 0) {
+            break;
+        }
+
+        for (preferences, (cursor, shares)) in preferences
+            .iter()
+            .zip(cursors.iter().zip(shares.iter_mut()))
+        {
+            if let Some(&category) = preferences.get(*cursor) {
+                match shares.last_mut() {
+                    Some((last, share)) if *last == category => *share += duration,
+                    _ => shares.push((category, duration)),
+                }
+            }
+        }
+        for (left, &eaters) in left.iter_mut().zip(eaters.iter()) {
+            if eaters > 0 && *left / eaters as f64 <= duration {
+                *left = 0.0;
+            } else {
+                *left -= eaters as f64 * duration;
+            }
+        }
+
+        if duration >= 1.0 - time {
+            break;
+        }
+        time += duration;
+    }
+
+    shares
+}
+
+/// Draw the category index of every student from the shares, and place the students
+///
+/// Returns the placed students per category index and the students that are not placed.
+fn draw_placements(
+    students: &[Student],
+    categories: &[Category],
+    shares: &[Vec<(usize, f64)>],
+    mut rng: &mut impl Rng,
+) -> (StudentsPerCategory, Vec<OrderedStudent>) {
+    // Take the first match whose weight, added to the weights before it, passes the draw.
+    let draw: f64 = rng.gen();
+    let mut total = 0.0;
+    let mut placements: Vec<Option<usize>> = vec![None; students.len()];
+    for (weight, matched) in Decomposition::new(shares, categories.len()) {
+        placements = matched;
+        total += weight;
+        if total > draw {
+            break;
+        }
+    }
+
+    let mut placed: StudentsPerCategory = vec![Vec::new(); categories.len()];
+    let mut not_placable: Vec<OrderedStudent> = Vec::new();
+    for mut student in draw_order(students, categories, &mut rng) {
+        match placements[student.id] {
+            Some(category) => {
+                if student.preferences.contains(&category) {
+                    while student.next_preference().is_some_and(|c| c != category) {}
+                } else {
+                    student.rank = None;
+                }
+                placed[category].push(student);
+            }
+            None => not_placable.push(student),
+        }
+    }
+    (placed, not_placable)
+}
+
+/// Splits the shares of the students into matches with a weight each, which add up to the
+/// shares (Birkhoff-von Neumann)
+///
+/// Every category is split into seats that hold at most one student in total, which gives a
+/// matrix of students and seats in which no row or column adds up to more than one. Adding
+/// a column for every student and a row for every seat that take what is left gives a square
+/// matrix in which every row and column adds up to exactly one, which is a weighted sum of
+/// perfect matchings. Each step finds a perfect matching over the cells that are left, and
+/// takes its smallest cell from every cell of the matching.
+struct Decomposition {
+    student_count: usize,
+    /// Category index of every seat
+    seats: Vec<usize>,
+    /// Column and weight of the cells that are left, per row
+    rows: Vec<Vec<(usize, f64)>>,
+    /// Cell of the column every row is matched to
+    matched_cells: Vec<Option<usize>>,
+    /// Row every column is matched to
+    matched_rows: Vec<Option<usize>>,
+    /// Weight that is not split off yet
+    weight_left: f64,
+}
+
+impl Decomposition {
+    /// Rows are the students, then the seats. Columns are the seats, then the students.
+    fn new(shares: &[Vec<(usize, f64)>], category_count: usize) -> Self {
+        let student_count = shares.len();
+        let mut columns: Vec<Vec<(usize, f64)>> = vec![Vec::new(); category_count];
+        for (student, shares) in shares.iter().enumerate() {
+            for &(category, share) in shares.iter().filter(|&&(_, share)| share > EPSILON) {
+                columns[category].push((student, share));
+            }
+        }
+
+        // Fill the seats of every category one by one.
+        let mut seats: Vec<usize> = Vec::new();
+        let mut cells: Vec<(usize, usize, f64)> = Vec::new();
+        for (category, column) in columns.into_iter().enumerate() {
+            let mut filled = 1.0;
+            for (student, mut share) in column {
+                while share > EPSILON {
+                    if filled >= 1.0 - EPSILON {
+                        seats.push(category);
+                        filled = 0.0;
+                    }
+                    let part = share.min(1.0 - filled);
+                    cells.push((student, seats.len() - 1, part));
+                    filled += part;
+                    share -= part;
+                }
+            }
+        }
+
+        let seat_count = seats.len();
+        let mut rows: Vec<Vec<(usize, f64)>> = vec![Vec::new(); student_count + seat_count];
+        let mut seat_totals: Vec<f64> = vec![0.0; seat_count];
+        for &(student, seat, weight) in cells.iter() {
+            rows[student].push((seat, weight));
+            rows[student_count + seat].push((seat_count + student, weight));
+            seat_totals[seat] += weight;
+        }
+        for (student, row) in rows.iter_mut().take(student_count).enumerate() {
+            let left = 1.0 - row.iter().map(|&(_, w)| w).sum::<f64>();
+            if left > EPSILON {
+                row.push((seat_count + student, left));
+            }
+        }
+        for (seat, total) in seat_totals.into_iter().enumerate() {
+            if 1.0 - total > EPSILON {
+                rows[student_count + seat].push((seat, 1.0 - total));
+            }
+        }
+
+        Decomposition {
+            student_count,
+            seats,
+            matched_cells: vec![None; rows.len()],
+            matched_rows: vec![None; rows.len()],
+            rows,
+            weight_left: 1.0,
+        }
+    }
+
+    /// Match a row that is not matched, moving other rows to another column where needed
+    ///
+    /// Returns `false` when no column can be freed for the row.
+    fn augment(&mut self, start: usize) -> bool {
+        // Row and cell through which every column was reached.
+        let mut reached: Vec<Option<(usize, usize)>> = vec![None; self.matched_rows.len()];
+        let mut queue: VecDeque<usize> = VecDeque::from(vec![start]);
+
+        while let Some(row) = queue.pop_front() {
+            for (cell, &(column, weight)) in self.rows[row].iter().enumerate() {
+                if weight <= 0.0 || reached[column].is_some() {
+                    continue;
+                }
+                reached[column] = Some((row, cell));
+
+                if let Some(next_row) = self.matched_rows[column] {
+                    queue.push_back(next_row);
+                    continue;
+                }
+
+                // Move every row on the path to the column it was reached through.
+                let mut column = column;
+                while let Some((row, cell)) = reached[column] {
+                    let previous = self.matched_cells[row].map(|c| self.rows[row][c].0);
+                    self.matched_cells[row] = Some(cell);
+                    self.matched_rows[column] = Some(row);
+                    match previous {
+                        Some(previous) => column = previous,
+                        None => break,
+                    }
+                }
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Iterator for Decomposition {
+    /// Weight of the match and the category index of every student
+    type Item = (f64, Vec<Option<usize>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.weight_left <= EPSILON {
+            return None;
+        }
+        for row in 0..self.rows.len() {
+            if self.matched_cells[row].is_none() && !self.augment(row) {
+                return None;
+            }
+        }
+
+        let cells: Vec<(usize, usize)> = self
+            .matched_cells
+            .iter()
+            .enumerate()
+            .filter_map(|(row, cell)| cell.map(|cell| (row, cell)))
+            .collect();
+        let weight = cells
+            .iter()
+            .map(|&(row, cell)| self.rows[row][cell].1)
+            .fold(self.weight_left, f64::min);
+        let placements = cells[..self.student_count]
+            .iter()
+            .map(|&(row, cell)| self.seats.get(self.rows[row][cell].0).copied())
+            .collect();
+
+        // Cells that are used up leave the matching.
+        for (row, cell) in cells {
+            let (column, cell_weight) = &mut self.rows[row][cell];
+            *cell_weight -= weight;
+            if *cell_weight <= EPSILON {
+                *cell_weight = 0.0;
+                self.matched_rows[*column] = None;
+                self.matched_cells[row] = None;
+            }
+        }
+        self.weight_left -= weight;
+
+        Some((weight, placements))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decomposition_adds_up_to_shares() {
+        // Cooking has two seats, reading one
+        let shares = vec![
+            vec![(0, 0.5), (1, 0.25)],
+            vec![(0, 0.75), (1, 0.25)],
+            vec![(0, 0.75), (1, 0.25)],
+        ];
+
+        let mut totals = vec![vec![0.0; 2]; 3];
+        let mut weight = 0.0;
+        for (term_weight, placements) in Decomposition::new(&shares, 2) {
+            let mut seats = [0; 2];
+            for (student, placement) in placements.into_iter().enumerate() {
+                if let Some(category) = placement {
+                    totals[student][category] += term_weight;
+                    seats[category] += 1;
+                }
+            }
+            assert!(seats[0] <= 2 && seats[1] <= 1);
+            weight += term_weight;
+        }
+
+        assert!((weight - 1.0).abs() < EPSILON);
+        for (student, shares) in shares.iter().enumerate() {
+            for &(category, share) in shares.iter() {
+                assert!((totals[student][category] - share).abs() < EPSILON);
+            }
+        }
+    }
+}
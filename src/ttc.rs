@@ -0,0 +1,420 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Implements the Top Trading Cycles algorithm
+//!
+//! Every category with open spots points to the student it prefers most (the student with the
+//! highest priority, the lottery breaks ties), and every student points to the category they
+//! prefer most. Each round the students in a cycle are placed in the category they point to.
+//! The result is Pareto-efficient: no two students can swap their spots and both be better off.
+
+use super::da_stb::{assign_fallback, draw_order};
+use super::validation::{reject_fixed_placements, reject_groups, reject_reserves};
+use super::{
+    validate, Category, Fallback, MatchError, MatchResult, Matcher, OrderedStudent, Student,
+    StudentsPerCategory,
+};
+use rand::prelude::*;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Top Trading Cycles mechanism
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Ttc;
+
+impl Matcher for Ttc {
+    fn match_unchecked(
+        &self,
+        students: &[Student],
+        categories: &[Category],
+        fallback: &Fallback,
+        mut rng: &mut dyn RngCore,
+    ) -> MatchResult {
+        match_students_unchecked(students, categories, fallback, &mut rng)
+    }
+
+    fn validate(&self, students: &[Student], categories: &[Category]) -> Result<(), MatchError> {
+        validate(students, categories)?;
+        reject_groups(students)?;
+        reject_reserves(categories)?;
+        reject_fixed_placements(categories)?;
+        Ok(())
+    }
+}
+
+/// Match students to categories
+///
+/// Use this function if each student can only be placed in one category
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::ttc::match_students;
+/// use matchmaker::{Category, Student};
+/// use rand::thread_rng;
+/// use std::collections::VecDeque;
+///
+/// // Create categories
+/// let cooking = Category::new("Cooking", 10);
+/// let reading = Category::new("Reading", 10);
+/// let walking = Category::new("Walking", 5);
+///
+/// // Create student Bert
+/// // Bert wishes to be placed in category cooking or reading (in that order)
+/// let bert = Student::new(
+///     "Bert",
+///     VecDeque::from(vec![cooking.clone(), reading.clone()]),
+///     Vec::new(),
+/// );
+///
+/// // Create student Suze
+/// // Suze wishes to be placed in category reading or cooking (in that order),
+/// // but does not wish to be placed in category walking
+/// let suze = Student::new(
+///     "Suze",
+///     VecDeque::from(vec![reading.clone(), cooking.clone()]),
+///     Vec::from([walking.clone()]),
+/// );
+///
+/// let mut rng = thread_rng();
+/// let categories = Vec::from([cooking, reading, walking]);
+///
+/// let match_result = match_students(Vec::from([bert, suze]), &categories, &mut rng).unwrap();
+///
+/// assert_eq!(match_result.placed.get("Cooking").unwrap()[0], "Bert");
+/// assert_eq!(match_result.placed.get("Reading").unwrap()[0], "Suze");
+/// assert!(match_result.not_placable.is_empty());
+/// ```
+///
+/// # Errors
+///
+/// Returns [`MatchError::InvalidInput`] when the students and categories are inconsistent,
+/// see [`validate`], or when students are part of a [`Group`] or categories have reserved
+/// seats or fixed placements, see [`Violation`].
+///
+/// [`MatchError::InvalidInput`]: ../enum.MatchError.html#variant.InvalidInput
+/// [`validate`]: ../fn.validate.html
+/// [`Group`]: ../struct.Group.html
+/// [`Violation`]: ../enum.Violation.html
+pub fn match_students(
+    students: Vec<Student>,
+    categories: &[Category],
+    rng: &mut impl Rng,
+) -> Result<MatchResult, MatchError> {
+    Ttc.match_students(students, categories, rng)
+}
+
+pub(crate) fn match_students_unchecked(
+    students: &[Student],
+    categories: &[Category],
+    fallback: &Fallback,
+    mut rng: &mut impl Rng,
+) -> MatchResult {
+    let unplaced_students = draw_order(students, categories, &mut rng);
+    let mut placed: StudentsPerCategory = vec![Vec::new(); categories.len()];
+
+    // Trade spots until every student is either placed or out of preferences
+    let not_placable = trade(unplaced_students, &mut placed, categories);
+
+    // Assign unplaced students to open spots by the fallback policy.
+    let not_placable = assign_fallback(not_placable, &mut placed, categories, fallback, &mut rng);
+
+    MatchResult::from(placed, not_placable, Vec::new(), categories)
+}
+
+/// Run the trading rounds and return the students that could not be placed
+///
+/// Instead of building every pointer again after each cycle, a path of pointers is followed
+/// until it runs into itself. After a cycle is placed, the path is kept up to the first
+/// student that has to point elsewhere, so every pointer is only followed a few times.
+fn trade(
+    students: Vec<OrderedStudent>,
+    placed: &mut StudentsPerCategory,
+    categories: &[Category],
+) -> Vec<OrderedStudent> {
+    let mut pool = TradingPool::new(students, categories);
+    let mut path: Vec<usize> = Vec::new();
+    let mut on_path: Vec<Option<usize>> = vec![None; pool.students.len()];
+    let mut start = 0;
+
+    loop {
+        // Start a new path at the first student that still takes part.
+        let current = match path.last() {
+            Some(&current) => current,
+            None => {
+                while start < pool.students.len() && !pool.point(start) {
+                    start += 1;
+                }
+                if start == pool.students.len() {
+                    break;
+                }
+                on_path[start] = Some(0);
+                path.push(start);
+                start
+            }
+        };
+
+        // The category of a student on the path may have been filled by a cycle, and a
+        // student out of preferences leaves the path.
+        if !pool.point(current) {
+            on_path[current] = None;
+            path.pop();
+            continue;
+        }
+        let category = pool.pointer(current).unwrap();
+
+        // The student itself still takes part, so the category has a best student.
+        let best = pool.best_student(category).unwrap();
+        match on_path[best] {
+            None => {
+                on_path[best] = Some(path.len());
+                path.push(best);
+            }
+            Some(position) => {
+                let cycle = path.split_off(position);
+                for &i in cycle.iter() {
+                    on_path[i] = None;
+                }
+                pool.place(cycle);
+
+                // Keep the path up to the first student whose category is full now, and let
+                // the last student point again.
+                if let Some(full) = path
+                    .iter()
+                    .position(|&i| pool.pointer(i).is_none())
+                    .map(|position| position + 1)
+                {
+                    for &i in path[full..].iter() {
+                        on_path[i] = None;
+                    }
+                    path.truncate(full);
+                }
+            }
+        }
+    }
+
+    pool.finish(placed)
+}
+
+/// Students and categories taking part in the trading rounds
+struct TradingPool {
+    students: Vec<OrderedStudent>,
+    /// Whether the student is neither placed nor out of preferences
+    active: Vec<bool>,
+    spots_available: Vec<usize>,
+    /// Indices of the students by lot
+    by_order: Vec<usize>,
+    /// Indices of the students with a priority, by priority and lot, per category index
+    by_priority: Vec<Vec<usize>>,
+    /// Position of the best student per category index, in `by_priority` followed by
+    /// `by_order`
+    cursors: Vec<usize>,
+    /// Students placed per category index, in the order they were placed
+    placements: Vec<Vec<usize>>,
+    not_placable: Vec<usize>,
+}
+
+impl TradingPool {
+    fn new(students: Vec<OrderedStudent>, categories: &[Category]) -> Self {
+        let mut by_order: Vec<usize> = (0..students.len()).collect();
+        by_order.sort_by_key(|&i| students[i].order);
+
+        let ids: HashMap<&str, usize> = students
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.name.as_str(), i))
+            .collect();
+        let by_priority = categories
+            .iter()
+            .map(|category| {
+                let mut prioritized: Vec<usize> = category
+                    .priorities
+                    .iter()
+                    .filter(|&(_, &priority)| priority > 0)
+                    .filter_map(|(name, _)| ids.get(name.as_str()).copied())
+                    .collect();
+                prioritized.sort_by_key(|&i| {
+                    (
+                        Reverse(category.priority(&students[i].name)),
+                        students[i].order,
+                    )
+                });
+                prioritized
+            })
+            .collect();
+
+        TradingPool {
+            active: vec![true; students.len()],
+            spots_available: categories.iter().map(|c| c.max_placements).collect(),
+            by_order,
+            by_priority,
+            cursors: vec![0; categories.len()],
+            placements: vec![Vec::new(); categories.len()],
+            not_placable: Vec::new(),
+            students,
+        }
+    }
+
+    /// Let the student point to its most preferred category that still has open spots, and
+    /// return whether it still takes part
+    fn point(&mut self, i: usize) -> bool {
+        if !self.active[i] {
+            return false;
+        }
+        let student = &mut self.students[i];
+        while let Some(&category) = student.preferences.front() {
+            if student.excludes(category) || self.spots_available[category] == 0 {
+                student.next_preference();
+            } else {
+                return true;
+            }
+        }
+        self.active[i] = false;
+        self.not_placable.push(i);
+        false
+    }
+
+    /// Return the category the student points to, or `None` when it has to point again
+    fn pointer(&self, i: usize) -> Option<usize> {
+        self.students[i]
+            .preferences
+            .front()
+            .copied()
+            .filter(|&category| self.spots_available[category] > 0)
+    }
+
+    /// Return the student with the highest priority for the category that still takes
+    /// part, using the lottery to break ties
+    fn best_student(&mut self, category: usize) -> Option<usize> {
+        loop {
+            let cursor = self.cursors[category];
+            let prioritized = &self.by_priority[category];
+            let i = match prioritized.get(cursor) {
+                Some(&i) => i,
+                None => *self.by_order.get(cursor - prioritized.len())?,
+            };
+            if self.point(i) {
+                return Some(i);
+            }
+            self.cursors[category] += 1;
+        }
+    }
+
+    /// Place every student of a cycle in the category it points to
+    fn place(&mut self, mut cycle: Vec<usize>) {
+        cycle.sort_unstable();
+        for i in cycle {
+            let category = self.students[i].next_preference().unwrap();
+            self.spots_available[category] -= 1;
+            self.placements[category].push(i);
+            self.active[i] = false;
+        }
+    }
+
+    /// Move the placed students to `placed` and return the students that could not be placed
+    fn finish(self, placed: &mut StudentsPerCategory) -> Vec<OrderedStudent> {
+        let mut students: Vec<Option<OrderedStudent>> =
+            self.students.into_iter().map(Some).collect();
+        for (category, placements) in self.placements.into_iter().enumerate() {
+            placed[category].extend(placements.into_iter().filter_map(|i| students[i].take()));
+        }
+        let mut not_placable: Vec<OrderedStudent> = self
+            .not_placable
+            .into_iter()
+            .filter_map(|i| students[i].take())
+            .collect();
+        not_placable.sort();
+        not_placable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeSet, VecDeque};
+
+    #[test]
+    fn test_trade() {
+        let cooking = Category::new("Cooking", 1);
+        let reading = Category::new("Reading", 1);
+
+        let bert = OrderedStudent {
+            name: "Bert".into(),
+            id: 0,
+            preferences: VecDeque::from(vec![0, 1]),
+            exclude: Vec::new(),
+            order: 1,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let kate = OrderedStudent {
+            name: "Kate".into(),
+            id: 1,
+            preferences: VecDeque::from(vec![0]),
+            exclude: Vec::new(),
+            order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+        let harry = OrderedStudent {
+            name: "Harry".into(),
+            id: 2,
+            preferences: VecDeque::from(vec![1]),
+            exclude: Vec::new(),
+            order: 2,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+
+        let mut placed = vec![Vec::new(); 2];
+        let categories = vec![cooking, reading];
+
+        let not_placable = trade(
+            vec![bert.clone(), kate.clone(), harry.clone()],
+            &mut placed,
+            &categories,
+        );
+
+        let placed_names = |category: usize| -> Vec<String> {
+            placed[category].iter().map(|s| s.name.clone()).collect()
+        };
+        assert_eq!(placed_names(0), vec!["Kate".to_string()]);
+        assert_eq!(placed_names(1), vec!["Bert".to_string()]);
+        assert_eq!(not_placable.len(), 1);
+        assert_eq!(not_placable[0].name, "Harry");
+    }
+
+    #[test]
+    fn test_trade_exclude() {
+        let cooking = Category::new("Cooking", 1);
+
+        let mut bert = OrderedStudent {
+            name: "Bert".into(),
+            id: 0,
+            preferences: VecDeque::from(vec![0]),
+            exclude: vec![0],
+            order: 0,
+            rank: None,
+            preference_ranks: VecDeque::new(),
+            group: None,
+            attributes: BTreeSet::new(),
+        };
+
+        let mut placed = vec![Vec::new()];
+        let not_placable = trade(vec![bert.clone()], &mut placed, &[cooking]);
+
+        bert.preferences.remove(0);
+        bert.rank = Some(1);
+        assert!(placed[0].is_empty());
+        assert_eq!(not_placable, vec![bert]);
+    }
+}
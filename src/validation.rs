@@ -0,0 +1,599 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Validates the input of a match
+
+use super::{Category, Fallback, Student};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Error returned when students can't be matched
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MatchError {
+    /// The students and categories are inconsistent. Holds every violation that was found.
+    InvalidInput(Vec<Violation>),
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchError::InvalidInput(violations) => {
+                write!(f, "invalid input:")?;
+                for violation in violations {
+                    write!(f, "\n - {}", violation)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MatchError {}
+
+/// Describes a single inconsistency in the input of a match
+///
+/// Most violations make the input invalid for every mechanism. [`UnsupportedGroup`],
+/// [`UnsupportedReserve`] and [`UnsupportedFixedPlacement`] report input that is consistent,
+/// but that the chosen mechanism can't keep: it would split a group, give a reserved seat to
+/// a student without the attribute or move a student away from a fixed placement.
+///
+/// [`UnsupportedGroup`]: #variant.UnsupportedGroup
+/// [`UnsupportedReserve`]: #variant.UnsupportedReserve
+/// [`UnsupportedFixedPlacement`]: #variant.UnsupportedFixedPlacement
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Violation {
+    /// More than one student has this name
+    DuplicateStudent(String),
+    /// More than one category has this name
+    DuplicateCategory(String),
+    /// The student is not part of the match
+    UnknownStudent(String),
+    /// The category can't hold any students
+    ZeroCapacity(String),
+    /// The category needs more students than it can hold
+    MinimumAboveCapacity(String),
+    /// A time slot of the category doesn't end after it starts
+    InvalidTimeSlot(String),
+    /// The category reserves more seats than it has
+    ReservesAboveCapacity(String),
+    /// The category has more fixed placements than seats
+    FixedPlacementsAboveCapacity(String),
+    /// The student has a fixed placement in more than one category
+    MultipleFixedPlacements(String),
+    /// The seed of a lottery is not a hex encoded 32 byte value
+    InvalidSeed(String),
+    /// The input of a replayed lottery doesn't match the input hash of the earlier draw
+    ChangedInput(String),
+    /// A student refers to a category that is not part of the match
+    UnknownCategory {
+        /// Name of the student
+        student: String,
+        /// Name of the unknown category
+        category: String,
+    },
+    /// The indifference groups of the student don't add up to its preferences
+    InvalidIndifferenceGroups(String),
+    /// Members of the group have different preferences or constraints
+    InconsistentGroup(String),
+    /// The matching mechanism can't place the group together
+    UnsupportedGroup(String),
+    /// The matching mechanism can't keep the reserved seats of the category
+    UnsupportedReserve(String),
+    /// The matching mechanism can't keep the fixed placements of the category
+    UnsupportedFixedPlacement(String),
+    /// A student both prefers and excludes a category
+    ExcludedPreference {
+        /// Name of the student
+        student: String,
+        /// Name of the category
+        category: String,
+    },
+    /// The probabilities of the student or category are negative, or add up to more than one
+    /// placement or the capacity of the category
+    InvalidProbabilities(String),
+    /// A student has a fixed placement in a category it excludes
+    ExcludedFixedPlacement {
+        /// Name of the student
+        student: String,
+        /// Name of the category
+        category: String,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::DuplicateStudent(name) => write!(f, "student {} is not unique", name),
+            Violation::DuplicateCategory(name) => write!(f, "category {} is not unique", name),
+            Violation::UnknownStudent(name) => {
+                write!(f, "student {} is not part of the match", name)
+            }
+            Violation::ZeroCapacity(name) => {
+                write!(f, "category {} has no placements", name)
+            }
+            Violation::MinimumAboveCapacity(name) => {
+                write!(f, "category {} needs more students than it can hold", name)
+            }
+            Violation::InvalidTimeSlot(name) => {
+                write!(
+                    f,
+                    "category {} has a time slot that doesn't end after it starts",
+                    name
+                )
+            }
+            Violation::ReservesAboveCapacity(name) => {
+                write!(f, "category {} reserves more seats than it has", name)
+            }
+            Violation::FixedPlacementsAboveCapacity(name) => {
+                write!(f, "category {} has more fixed placements than seats", name)
+            }
+            Violation::MultipleFixedPlacements(name) => write!(
+                f,
+                "student {} has a fixed placement in more than one category",
+                name
+            ),
+            Violation::InvalidSeed(seed) => write!(f, "seed {} is not valid", seed),
+            Violation::ChangedInput(input_hash) => {
+                write!(f, "input doesn't match input hash {}", input_hash)
+            }
+            Violation::InconsistentGroup(name) => write!(
+                f,
+                "members of group {} have different preferences or constraints",
+                name
+            ),
+            Violation::UnsupportedGroup(name) => write!(
+                f,
+                "group {} can't be placed together by this mechanism",
+                name
+            ),
+            Violation::UnsupportedReserve(name) => write!(
+                f,
+                "reserved seats of category {} can't be kept by this mechanism",
+                name
+            ),
+            Violation::UnsupportedFixedPlacement(name) => write!(
+                f,
+                "fixed placements of category {} can't be kept by this mechanism",
+                name
+            ),
+            Violation::InvalidIndifferenceGroups(name) => write!(
+                f,
+                "indifference groups of student {} don't match its preferences",
+                name
+            ),
+            Violation::UnknownCategory { student, category } => write!(
+                f,
+                "student {} refers to unknown category {}",
+                student, category
+            ),
+            Violation::ExcludedPreference { student, category } => write!(
+                f,
+                "student {} both prefers and excludes category {}",
+                student, category
+            ),
+            Violation::InvalidProbabilities(name) => write!(
+                f,
+                "probabilities of {} are negative or add up to more than its placements",
+                name
+            ),
+            Violation::ExcludedFixedPlacement { student, category } => write!(
+                f,
+                "student {} has a fixed placement in excluded category {}",
+                student, category
+            ),
+        }
+    }
+}
+
+/// Check students and categories for inconsistencies
+///
+/// All matching functions call this function before matching, but it can also be used
+/// to check input up front.
+///
+/// # Arguments
+///
+/// * `students` - Students to match
+/// * `categories` - Categories to match the students to
+///
+/// # Example
+///
+/// ```
+/// use matchmaker::{validate, Category, MatchError, Student, Violation};
+/// use std::collections::VecDeque;
+///
+/// let cooking = Category::new("Cooking", 10);
+/// let reading = Category::new("Reading", 10);
+///
+/// // Reading is not one of the categories below
+/// let bert = Student::new("Bert", VecDeque::from(vec![reading]), Vec::new());
+///
+/// assert_eq!(
+///     validate(&[bert], &[cooking]),
+///     Err(MatchError::InvalidInput(vec![Violation::UnknownCategory {
+///         student: "Bert".into(),
+///         category: "Reading".into(),
+///     }]))
+/// );
+/// ```
+pub fn validate(students: &[Student], categories: &[Category]) -> Result<(), MatchError> {
+    let mut violations: Vec<Violation> = Vec::new();
+
+    let mut category_names: HashSet<&str> = HashSet::with_capacity(categories.len());
+    for category in categories {
+        if !category_names.insert(&category.name) {
+            violations.push(Violation::DuplicateCategory(category.name.clone()));
+        }
+        if category.max_placements == 0 {
+            violations.push(Violation::ZeroCapacity(category.name.clone()));
+        }
+        if category.min_placements > category.max_placements {
+            violations.push(Violation::MinimumAboveCapacity(category.name.clone()));
+        }
+        if category
+            .time_slots
+            .iter()
+            .any(|slot| slot.end <= slot.start)
+        {
+            violations.push(Violation::InvalidTimeSlot(category.name.clone()));
+        }
+        if category.reserves.iter().map(|r| r.seats).sum::<usize>() > category.max_placements {
+            violations.push(Violation::ReservesAboveCapacity(category.name.clone()));
+        }
+        if category.fixed_placements.len() > category.max_placements {
+            violations.push(Violation::FixedPlacementsAboveCapacity(
+                category.name.clone(),
+            ));
+        }
+    }
+
+    let mut student_names: HashSet<&str> = HashSet::with_capacity(students.len());
+    for student in students {
+        if !student_names.insert(&student.name) {
+            violations.push(Violation::DuplicateStudent(student.name.clone()));
+        }
+
+        for category in student.preferences.iter().chain(student.exclude.iter()) {
+            if !category_names.contains(category.name.as_str()) {
+                violations.push(Violation::UnknownCategory {
+                    student: student.name.clone(),
+                    category: category.name.clone(),
+                });
+            }
+        }
+
+        if !student.indifference_groups.is_empty()
+            && (student.indifference_groups.contains(&0)
+                || student.indifference_groups.iter().sum::<usize>() != student.preferences.len())
+        {
+            violations.push(Violation::InvalidIndifferenceGroups(student.name.clone()));
+        }
+
+        for category in student.preferences.iter() {
+            if student.exclude.contains(category) {
+                violations.push(Violation::ExcludedPreference {
+                    student: student.name.clone(),
+                    category: category.name.clone(),
+                });
+            }
+        }
+    }
+
+    let mut students_by_name: HashMap<&str, &Student> = HashMap::with_capacity(students.len());
+    for student in students {
+        students_by_name.entry(&student.name).or_insert(student);
+    }
+    let mut fixed_students: HashSet<&str> = HashSet::new();
+    for category in categories {
+        for name in category.fixed_placements.iter() {
+            let student = match students_by_name.get(name.as_str()) {
+                Some(student) => student,
+                None => {
+                    violations.push(Violation::UnknownStudent(name.clone()));
+                    continue;
+                }
+            };
+            if student.exclude.contains(category) {
+                violations.push(Violation::ExcludedFixedPlacement {
+                    student: name.clone(),
+                    category: category.name.clone(),
+                });
+            }
+            if !fixed_students.insert(name) {
+                violations.push(Violation::MultipleFixedPlacements(name.clone()));
+            }
+        }
+    }
+
+    // Members of a group apply together, so they need the same preferences and limits.
+    let mut groups: HashMap<&str, &Student> = HashMap::new();
+    for student in students {
+        if let Some(group) = &student.group {
+            match groups.get(group.name.as_str()) {
+                Some(first) => {
+                    let consistent = first.group == student.group
+                        && first
+                            .preferences
+                            .iter()
+                            .map(|c| &c.name)
+                            .eq(student.preferences.iter().map(|c| &c.name))
+                        && first.indifference_groups == student.indifference_groups
+                        && first.max_categories == student.max_categories;
+                    let reported =
+                        violations.contains(&Violation::InconsistentGroup(group.name.clone()));
+                    if !consistent && !reported {
+                        violations.push(Violation::InconsistentGroup(group.name.clone()));
+                    }
+                }
+                None => {
+                    groups.insert(&group.name, student);
+                }
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(MatchError::InvalidInput(violations))
+    }
+}
+
+/// Report every group, for mechanisms that can't place groups together
+pub(crate) fn reject_groups(students: &[Student]) -> Result<(), MatchError> {
+    let mut violations: Vec<Violation> = Vec::new();
+    for group in students.iter().filter_map(|s| s.group.as_ref()) {
+        let violation = Violation::UnsupportedGroup(group.name.clone());
+        if !violations.contains(&violation) {
+            violations.push(violation);
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(MatchError::InvalidInput(violations))
+    }
+}
+
+/// Report every category with reserved seats, for mechanisms that can't keep them
+pub(crate) fn reject_reserves(categories: &[Category]) -> Result<(), MatchError> {
+    let violations: Vec<Violation> = categories
+        .iter()
+        .filter(|c| !c.reserves.is_empty())
+        .map(|c| Violation::UnsupportedReserve(c.name.clone()))
+        .collect();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(MatchError::InvalidInput(violations))
+    }
+}
+
+/// Report every category with fixed placements, for mechanisms that can't keep them
+pub(crate) fn reject_fixed_placements(categories: &[Category]) -> Result<(), MatchError> {
+    let violations: Vec<Violation> = categories
+        .iter()
+        .filter(|c| !c.fixed_placements.is_empty())
+        .map(|c| Violation::UnsupportedFixedPlacement(c.name.clone()))
+        .collect();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(MatchError::InvalidInput(violations))
+    }
+}
+
+/// Report students and categories of the fallback policy that are not part of the match
+pub(crate) fn validate_fallback(
+    fallback: &Fallback,
+    students: &[Student],
+    categories: &[Category],
+) -> Result<(), MatchError> {
+    let category_names: Vec<(&String, Vec<&String>)> = match fallback {
+        Fallback::None | Fallback::Random => Vec::new(),
+        Fallback::LowestCost(costs) => costs
+            .iter()
+            .map(|(student, costs)| (student, costs.keys().collect()))
+            .collect(),
+        Fallback::SecondRound(preferences) => preferences
+            .iter()
+            .map(|(student, preferences)| (student, preferences.iter().collect()))
+            .collect(),
+    };
+
+    let mut violations: Vec<Violation> = Vec::new();
+    for (student, names) in category_names {
+        if !students.iter().any(|s| &s.name == student) {
+            violations.push(Violation::UnknownStudent(student.clone()));
+        }
+        for name in names {
+            if !categories.iter().any(|c| &c.name == name) {
+                violations.push(Violation::UnknownCategory {
+                    student: student.clone(),
+                    category: name.clone(),
+                });
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(MatchError::InvalidInput(violations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Group;
+    use std::collections::{BTreeMap, VecDeque};
+
+    #[test]
+    fn test_validate() {
+        let cooking = Category::new("Cooking", 3);
+        let reading = Category::new("Reading", 2);
+
+        let bert = Student::new(
+            "Bert",
+            VecDeque::from(vec![cooking.clone(), reading.clone()]),
+            Vec::new(),
+        );
+        let suze = Student::new("Suze", VecDeque::new(), vec![reading.clone()]);
+
+        assert_eq!(validate(&[bert, suze], &[cooking, reading]), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_lists_every_violation() {
+        let cooking = Category::new("Cooking", 3);
+        let reading = Category::new("Reading", 0);
+        let walking = Category::new("Walking", 1);
+        let swimming = Category::new("Swimming", 1)
+            .with_min_placements(2)
+            .with_time_slot(60, 30)
+            .with_reserve("low-income", 2)
+            .with_fixed_placement("Bert")
+            .with_fixed_placement("Kate");
+
+        let bert = Student::new(
+            "Bert",
+            VecDeque::from(vec![cooking.clone(), walking.clone()]),
+            vec![cooking.clone()],
+        );
+        let mut other_bert = Student::new("Bert", VecDeque::new(), Vec::new());
+        other_bert.indifference_groups = vec![1];
+
+        let result = validate(
+            &[bert, other_bert],
+            &[
+                cooking.clone().with_fixed_placement("Bert"),
+                reading,
+                cooking.clone(),
+                swimming,
+            ],
+        );
+
+        assert_eq!(
+            result,
+            Err(MatchError::InvalidInput(vec![
+                Violation::ZeroCapacity("Reading".into()),
+                Violation::DuplicateCategory("Cooking".into()),
+                Violation::MinimumAboveCapacity("Swimming".into()),
+                Violation::InvalidTimeSlot("Swimming".into()),
+                Violation::ReservesAboveCapacity("Swimming".into()),
+                Violation::FixedPlacementsAboveCapacity("Swimming".into()),
+                Violation::UnknownCategory {
+                    student: "Bert".into(),
+                    category: "Walking".into(),
+                },
+                Violation::ExcludedPreference {
+                    student: "Bert".into(),
+                    category: "Cooking".into(),
+                },
+                Violation::DuplicateStudent("Bert".into()),
+                Violation::InvalidIndifferenceGroups("Bert".into()),
+                Violation::ExcludedFixedPlacement {
+                    student: "Bert".into(),
+                    category: "Cooking".into(),
+                },
+                Violation::MultipleFixedPlacements("Bert".into()),
+                Violation::UnknownStudent("Kate".into()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_validate_excluded_fixed_placement() {
+        let cooking = Category::new("Cooking", 3);
+        let reading = Category::new("Reading", 2);
+
+        let bert = Student::new(
+            "Bert",
+            VecDeque::from(vec![reading.clone()]),
+            vec![cooking.clone()],
+        );
+
+        assert_eq!(
+            validate(&[bert], &[cooking.with_fixed_placement("Bert"), reading]),
+            Err(MatchError::InvalidInput(vec![
+                Violation::ExcludedFixedPlacement {
+                    student: "Bert".into(),
+                    category: "Cooking".into(),
+                }
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_validate_inconsistent_group() {
+        let cooking = Category::new("Cooking", 3);
+        let reading = Category::new("Reading", 2);
+
+        let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new())
+            .with_group(Group::hard("Siblings"));
+        let suze = Student::new("Suze", VecDeque::from(vec![reading.clone()]), Vec::new())
+            .with_group(Group::hard("Siblings"));
+        let kate = Student::new("Kate", VecDeque::from(vec![cooking.clone()]), Vec::new())
+            .with_group(Group::soft("Siblings"));
+
+        assert_eq!(
+            validate(&[bert, suze, kate], &[cooking, reading]),
+            Err(MatchError::InvalidInput(vec![
+                Violation::InconsistentGroup("Siblings".into())
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_validate_group_with_different_max_categories() {
+        let cooking = Category::new("Cooking", 3);
+        let reading = Category::new("Reading", 2);
+        let preferences = VecDeque::from(vec![cooking.clone(), reading.clone()]);
+
+        let bert = Student::new("Bert", preferences.clone(), Vec::new())
+            .with_group(Group::hard("Siblings"))
+            .with_max_categories(1);
+        let suze =
+            Student::new("Suze", preferences, Vec::new()).with_group(Group::hard("Siblings"));
+        let categories = [cooking, reading];
+
+        assert_eq!(
+            validate(&[bert.clone(), suze.clone()], &categories),
+            Err(MatchError::InvalidInput(vec![
+                Violation::InconsistentGroup("Siblings".into())
+            ]))
+        );
+        assert_eq!(
+            validate(&[bert, suze.with_max_categories(1)], &categories),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_validate_fallback() {
+        let cooking = Category::new("Cooking", 3);
+        let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new());
+
+        let mut preferences = BTreeMap::new();
+        preferences.insert("Bert".to_string(), vec!["Cooking".to_string()]);
+        preferences.insert("Suze".to_string(), vec!["Reading".to_string()]);
+
+        assert_eq!(
+            validate_fallback(&Fallback::SecondRound(preferences), &[bert], &[cooking]),
+            Err(MatchError::InvalidInput(vec![
+                Violation::UnknownStudent("Suze".into()),
+                Violation::UnknownCategory {
+                    student: "Suze".into(),
+                    category: "Reading".into(),
+                },
+            ]))
+        );
+    }
+}
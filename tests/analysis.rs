@@ -0,0 +1,168 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::get_data;
+use matchmaker::{
+    analysis::{find_blocking_pairs, simulate, BlockingPair},
+    da_mtb,
+    da_stb::{match_students, DaStb},
+    Category, Student,
+};
+use rand::rngs::{mock::StepRng, StdRng};
+use rand::SeedableRng;
+use std::collections::VecDeque;
+
+#[test]
+fn test_deferred_acceptance_is_stable() {
+    let (students, categories) = get_data(1, 1, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        find_blocking_pairs(&students, &categories, &match_result).unwrap(),
+        vec![]
+    );
+}
+
+#[test]
+fn test_multiple_tie_break_is_stable() {
+    let (students, categories) = get_data(1, 1, 1);
+
+    // Every category has its own lottery, which the fallback order doesn't follow
+    for seed in 0..50 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let match_result = da_mtb::match_students(students.clone(), &categories, &mut rng).unwrap();
+
+        assert_eq!(
+            find_blocking_pairs(&students, &categories, &match_result).unwrap(),
+            vec![],
+            "Seed {}",
+            seed
+        );
+    }
+}
+
+#[test]
+fn test_manual_override_breaks_stability() {
+    let (students, categories) = get_data(1, 1, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let mut match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    // Move Suze out of category walking by hand. She has a better lot than Bert for cooking.
+    match_result.placed.remove(&categories[2].name);
+    match_result.not_placable.push(students[1].name.clone());
+
+    assert_eq!(
+        find_blocking_pairs(&students, &categories, &match_result).unwrap(),
+        vec![
+            BlockingPair {
+                student: "Suze".into(),
+                category: "Walking".into(),
+                displaced: None,
+            },
+            BlockingPair {
+                student: "Suze".into(),
+                category: "Cooking".into(),
+                displaced: Some("Bert".into()),
+            },
+            BlockingPair {
+                student: "Kate".into(),
+                category: "Walking".into(),
+                displaced: None,
+            },
+            BlockingPair {
+                student: "Harry".into(),
+                category: "Walking".into(),
+                displaced: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_reserved_seat_does_not_block() {
+    let cooking = Category::new("Cooking", 1).with_reserve("low-income", 1);
+    let reading = Category::new("Reading", 1);
+    let bert = Student::new(
+        "Bert",
+        VecDeque::from(vec![cooking.clone(), reading.clone()]),
+        Vec::new(),
+    );
+    let kate = Student::new("Kate", VecDeque::from(vec![cooking.clone()]), Vec::new())
+        .with_attribute("low-income");
+    let students = vec![bert, kate];
+    let categories = vec![cooking.clone(), reading];
+    let mut rng = StepRng::new(2, 0);
+
+    let mut match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+    assert_eq!(match_result.placed["Cooking"][0], "Kate");
+
+    // Bert has the better lot, but the only seat of cooking is reserved for Kate.
+    match_result.lottery_numbers.insert("Bert".into(), 1);
+    match_result.lottery_numbers.insert("Kate".into(), 2);
+    assert_eq!(
+        find_blocking_pairs(&students, &categories, &match_result).unwrap(),
+        vec![]
+    );
+
+    // Without the reserve, Bert takes the seat.
+    let categories = vec![Category::new("Cooking", 1), categories[1].clone()];
+    assert_eq!(
+        find_blocking_pairs(&students, &categories, &match_result).unwrap(),
+        vec![BlockingPair {
+            student: "Bert".into(),
+            category: "Cooking".into(),
+            displaced: Some("Kate".into()),
+        }]
+    );
+}
+
+#[test]
+fn test_simulate() {
+    let (students, categories) = get_data(1, 1, 1);
+
+    let simulation = simulate(&students, &categories, 200, 42, &DaStb).unwrap();
+
+    assert_eq!(simulation.runs, 200);
+    assert_eq!(
+        simulation,
+        simulate(&students, &categories, 200, 42, &DaStb).unwrap(),
+        "The same seed gives the same estimates"
+    );
+    assert_eq!(
+        simulation.placement_probabilities["Harry"]["Cooking"], 0.0,
+        "Harry excludes cooking"
+    );
+    for student in students.iter() {
+        let total: f64 = simulation.placement_probabilities[&student.name]
+            .values()
+            .sum::<f64>()
+            + simulation.not_placable_probabilities[&student.name];
+        assert!(
+            (total - 1.0).abs() < 1e-9,
+            "Every student ends up somewhere"
+        );
+    }
+
+    // Three spots for five students
+    let placed: f64 = simulation
+        .placement_probabilities
+        .values()
+        .flat_map(|p| p.values())
+        .sum();
+    assert!((placed - 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_simulate_invalid_input() {
+    let (students, categories) = get_data(1, 0, 1);
+
+    assert!(simulate(&students, &categories, 10, 42, &DaStb).is_err());
+}
@@ -0,0 +1,283 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#![cfg(feature = "cli")]
+
+use matchmaker::{Fallback, MatchResult};
+use std::fs;
+use std::process::{Command, Output};
+
+fn matchmaker(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_matchmaker"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_match_csv() {
+    let output = matchmaker(&[
+        "--seed",
+        "42",
+        "tests/data/categories.csv",
+        "tests/data/students.csv",
+    ]);
+    assert!(output.status.success());
+
+    let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+    let placed: usize = match_result.placed.values().map(|p| p.len()).sum();
+
+    assert_eq!(placed, 3, "Every category is full");
+    assert_eq!(match_result.not_placable.len(), 2);
+    assert!(match_result.seed.is_some());
+}
+
+#[test]
+fn test_match_json_is_reproducible() {
+    let result_path = format!("{}/reproducible.json", env!("CARGO_TARGET_TMPDIR"));
+    let args = [
+        "--algorithm",
+        "da-mtb",
+        "--mode",
+        "multiple",
+        "--seed",
+        "42",
+        "--output",
+        &result_path,
+        "tests/data/categories.json",
+        "tests/data/students.json",
+    ];
+    assert!(matchmaker(&args).status.success());
+    let result = fs::read(&result_path).unwrap();
+
+    let match_result: MatchResult = serde_json::from_slice(&result).unwrap();
+    assert!(match_result.seed.is_some());
+    assert!(match_result.input_hash.is_some());
+
+    let replayed = matchmaker(&[
+        "--algorithm",
+        "da-mtb",
+        "--mode",
+        "multiple",
+        "--replay",
+        &result_path,
+        "tests/data/categories.json",
+        "tests/data/students.json",
+    ]);
+    assert!(replayed.status.success());
+    assert_eq!(result, replayed.stdout);
+}
+
+#[test]
+fn test_replay_changed_input() {
+    let result_path = format!("{}/changed_input.json", env!("CARGO_TARGET_TMPDIR"));
+    let output = matchmaker(&[
+        "--seed",
+        "42",
+        "--output",
+        &result_path,
+        "tests/data/categories.json",
+        "tests/data/students.json",
+    ]);
+    assert!(output.status.success());
+
+    let replayed = matchmaker(&[
+        "--replay",
+        &result_path,
+        "tests/data/priorities.json",
+        "tests/data/students.json",
+    ]);
+
+    assert_eq!(replayed.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&replayed.stderr).contains("input doesn't match input hash"));
+}
+
+#[test]
+fn test_match_csv_with_ttc_to_multiple_categories() {
+    let output = matchmaker(&[
+        "--algorithm",
+        "ttc",
+        "--mode",
+        "multiple",
+        "tests/data/categories.csv",
+        "tests/data/students.csv",
+    ]);
+    assert!(output.status.success());
+
+    let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+    let placed: usize = match_result.placed.values().map(|p| p.len()).sum();
+
+    assert_eq!(placed, 3, "Every category is full");
+}
+
+#[test]
+fn test_match_invalid_input() {
+    let output = matchmaker(&[
+        "tests/data/categories.json",
+        "tests/data/unknown_category.json",
+    ]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("student Bert refers to unknown category Swimming"));
+}
+
+#[test]
+fn test_match_csv_with_groups() {
+    let output = matchmaker(&["tests/data/categories.csv", "tests/data/groups.csv"]);
+    assert!(output.status.success());
+
+    let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+    let mut not_placable: Vec<&str> = match_result
+        .not_placable
+        .iter()
+        .map(|s| s.as_str())
+        .collect();
+    not_placable.sort_unstable();
+
+    assert_eq!(match_result.placed["Reading"][0], "Kate");
+    assert_eq!(
+        not_placable,
+        vec!["Bert", "Suze"],
+        "Siblings don't fit anywhere"
+    );
+}
+
+#[test]
+fn test_match_csv_with_time_slots() {
+    let output = matchmaker(&[
+        "--mode",
+        "multiple",
+        "tests/data/time_slots.csv",
+        "tests/data/students.csv",
+    ]);
+    assert!(output.status.success());
+
+    let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+    for placements in match_result.placements.values() {
+        assert!(
+            !(placements.iter().any(|p| p.category == "Cooking")
+                && placements.iter().any(|p| p.category == "Reading")),
+            "Cooking and Reading take place at the same time"
+        );
+    }
+}
+
+#[test]
+fn test_match_csv_with_fallback() {
+    let fallback = |policy: &str| {
+        let output = matchmaker(&[
+            "--fallback",
+            policy,
+            "tests/data/categories.csv",
+            "tests/data/fallback.csv",
+        ]);
+        assert!(output.status.success());
+
+        let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+        let loser = match match_result.placed["Walking"][0].as_str() {
+            "Bert" => "Suze",
+            _ => "Bert",
+        };
+        let category = match_result
+            .placements
+            .get(loser)
+            .map(|p| p[0].category.clone());
+        (loser, category, match_result.fallback)
+    };
+
+    let (_, category, policy) = fallback("none");
+    assert_eq!(category, None);
+    assert_eq!(policy, Fallback::None);
+
+    let (loser, category, _) = fallback("lowest-cost");
+    let expected = if loser == "Bert" {
+        "Reading"
+    } else {
+        "Cooking"
+    };
+    assert_eq!(category.unwrap(), expected);
+
+    let (loser, category, _) = fallback("second-round");
+    let expected = if loser == "Bert" {
+        "Cooking"
+    } else {
+        "Reading"
+    };
+    assert_eq!(category.unwrap(), expected);
+}
+
+#[test]
+fn test_match_csv_with_reserves() {
+    let output = matchmaker(&["tests/data/reserves.csv", "tests/data/attributes.csv"]);
+    assert!(output.status.success());
+
+    let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(
+        match_result.placed["Walking"][0], "Suze",
+        "The only seat in walking is reserved for Suze"
+    );
+}
+
+#[test]
+fn test_match_csv_with_priorities() {
+    let output = matchmaker(&["tests/data/priorities.csv", "tests/data/consent.csv"]);
+    assert!(output.status.success());
+
+    let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(match_result.placed["Cooking"][0], "Suze");
+}
+
+#[test]
+fn test_match_csv_with_fixed_placements() {
+    let output = matchmaker(&[
+        "tests/data/fixed_placements.csv",
+        "tests/data/attributes.csv",
+    ]);
+    assert!(output.status.success());
+
+    let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(match_result.placed["Walking"][0], "Bert");
+    assert!(match_result.placements["Bert"][0].fixed);
+}
+
+#[test]
+fn test_match_csv_with_eadam() {
+    let output = matchmaker(&[
+        "--algorithm",
+        "eadam",
+        "tests/data/priorities.json",
+        "tests/data/consent.csv",
+    ]);
+    assert!(output.status.success());
+
+    let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(match_result.placed["Cooking"][0], "Suze");
+    assert_eq!(match_result.placed["Reading"][0], "Bert");
+    assert_eq!(match_result.waivers["Kate"], vec!["Cooking".to_string()]);
+}
+
+#[test]
+fn test_match_csv_with_probabilistic_serial() {
+    let output = matchmaker(&[
+        "--algorithm",
+        "probabilistic-serial",
+        "--seed",
+        "42",
+        "tests/data/priorities.json",
+        "tests/data/consent.csv",
+    ]);
+    assert!(output.status.success());
+
+    let match_result: MatchResult = serde_json::from_slice(&output.stdout).unwrap();
+    let placed: usize = match_result.placed.values().map(|p| p.len()).sum();
+
+    assert_eq!(placed, 3, "Every category is full");
+    assert_eq!(match_result.placements.len(), 3, "Every student is placed");
+}
@@ -0,0 +1,47 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Test data shared by the integration tests
+
+use matchmaker::{Category, Student};
+use std::collections::VecDeque;
+
+pub fn get_data(
+    cooking_mp: usize,
+    reading_mp: usize,
+    walking_mp: usize,
+) -> (Vec<Student>, Vec<Category>) {
+    let cooking = Category::new("Cooking", cooking_mp);
+    let reading = Category::new("Reading", reading_mp);
+    let walking = Category::new("Walking", walking_mp);
+
+    let bert = Student::new(
+        "Bert",
+        VecDeque::from(vec![cooking.clone(), reading.clone(), walking.clone()]),
+        Vec::new(),
+    );
+    let suze = Student::new(
+        "Suze",
+        VecDeque::from(vec![walking.clone(), cooking.clone()]),
+        Vec::new(),
+    );
+    let kate = Student::new(
+        "Kate",
+        VecDeque::from(vec![walking.clone(), reading.clone()]),
+        Vec::new(),
+    );
+    let harry = Student::new(
+        "Harry",
+        VecDeque::from(vec![walking.clone()]),
+        vec![cooking.clone()],
+    );
+    let lisa = Student::new("Lisa", VecDeque::new(), Vec::new());
+
+    let categories = vec![cooking, reading, walking];
+    let students = vec![bert, suze, kate, harry, lisa];
+
+    (students, categories)
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::get_data;
+use matchmaker::{
+    da_mtb::{match_students, match_students_to_multiple_categories},
+    Group, MatchError, Violation,
+};
+use rand::rngs::mock::StepRng;
+
+#[test]
+fn test_match_students() {
+    let (students, categories) = get_data(3, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![students[1].name.clone()],
+        "Suze is in category walking"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[1].name).unwrap(),
+        &vec![students[2].name.clone(), students[3].name.clone()],
+        "Kate and Harry are in category reading"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[0].name).unwrap(),
+        &vec![students[0].name.clone(), students[4].name.clone()],
+        "Bert and Lisa are in category cooking"
+    );
+    assert!(match_result.not_placable.is_empty(), "Everyone is placed");
+}
+
+#[test]
+fn test_match_students_not_enough_places() {
+    let (students, categories) = get_data(1, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[0].name).unwrap(),
+        &vec![students[0].name.clone()],
+        "Bert is in category cooking"
+    );
+    assert_eq!(
+        match_result.not_placable,
+        vec![students[4].name.clone()],
+        "Lisa was not placable"
+    );
+}
+
+#[test]
+fn test_match_students_to_multiple_categories() {
+    let (students, categories) = get_data(3, 1, 3);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result =
+        match_students_to_multiple_categories(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![
+            students[1].name.clone(),
+            students[2].name.clone(),
+            students[3].name.clone()
+        ],
+        "Suze, Kate and Harry are in category walking"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[1].name).unwrap(),
+        &vec![students[2].name.clone()],
+        "Kate is in category reading"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[0].name).unwrap(),
+        &vec![
+            students[0].name.clone(),
+            students[4].name.clone(),
+            students[1].name.clone()
+        ],
+        "Bert, Lisa and Suze are in category cooking"
+    );
+    assert!(match_result.not_placable.is_empty(), "Everyone is placed");
+}
+
+#[test]
+fn test_match_students_rejects_groups() {
+    let (mut students, categories) = get_data(3, 2, 1);
+    students[0].group = Some(Group::hard("Friends"));
+    students[4].group = Some(Group::hard("Friends"));
+    students[4].preferences = students[0].preferences.clone();
+    let mut rng = StepRng::new(2, 0);
+
+    assert_eq!(
+        match_students(students, &categories, &mut rng).unwrap_err(),
+        MatchError::InvalidInput(vec![Violation::UnsupportedGroup("Friends".into())])
+    );
+}
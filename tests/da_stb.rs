@@ -4,65 +4,40 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod common;
+
+use common::get_data;
 use matchmaker::{
-    da_stb::{match_students, match_students_to_multiple_categories},
-    Category, Student,
+    analysis::find_blocking_pairs,
+    da_stb::{
+        improve_placements, match_students, match_students_to_multiple_categories,
+        match_students_to_multiple_categories_with_options, match_students_with_fallback,
+        withdraw_students,
+    },
+    Category, Fallback, Group, MatchError, MatchResult, MultipleCategoryOptions, Placement,
+    Student, Violation,
 };
 use rand::rngs::mock::StepRng;
-use std::collections::VecDeque;
-
-fn get_data(
-    cooking_mp: usize,
-    reading_mp: usize,
-    walking_mp: usize,
-) -> (Vec<Student>, Vec<Category>) {
-    let cooking = Category::new("Cooking", cooking_mp);
-    let reading = Category::new("Reading", reading_mp);
-    let walking = Category::new("Walking", walking_mp);
-
-    let bert = Student::new(
-        "Bert",
-        VecDeque::from(vec![cooking.clone(), reading.clone(), walking.clone()]),
-        Vec::new(),
-    );
-    let suze = Student::new(
-        "Suze",
-        VecDeque::from(vec![walking.clone(), cooking.clone()]),
-        Vec::new(),
-    );
-    let kate = Student::new(
-        "Kate",
-        VecDeque::from(vec![walking.clone(), reading.clone()]),
-        Vec::new(),
-    );
-    let harry = Student::new(
-        "Harry",
-        VecDeque::from(vec![walking.clone()]),
-        vec![cooking.clone()],
-    );
-    let lisa = Student::new("Lisa", VecDeque::new(), Vec::new());
-
-    let categories = vec![cooking, reading, walking];
-    let students = vec![bert, suze, kate, harry, lisa];
-
-    (students, categories)
-}
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, VecDeque};
 
 #[test]
 fn test_match_students() {
     let (students, categories) = get_data(3, 2, 1);
     let mut rng = StepRng::new(2, 0);
 
-    let match_result = match_students(students.clone(), &categories, &mut rng);
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
 
     assert_eq!(
         match_result.placed.get(&categories[2].name).unwrap(),
-        &vec![students[1].clone()],
+        &vec![students[1].name.clone()],
         "Suze is in category walking"
     );
     assert_eq!(
         match_result.placed.get(&categories[1].name).unwrap(),
-        &vec![students[2].clone(), students[3].clone()],
+        &vec![students[2].name.clone(), students[3].name.clone()],
         "Kate is in category reading"
     );
     assert_eq!(
@@ -78,58 +53,58 @@ fn test_match_students_not_enough_places() {
     let (students, categories) = get_data(1, 2, 1);
     let mut rng = StepRng::new(2, 0);
 
-    let match_result = match_students(students.clone(), &categories, &mut rng);
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
 
     assert_eq!(
         match_result.placed.get(&categories[1].name).unwrap(),
-        &vec![students[2].clone(), students[3].clone()],
+        &vec![students[2].name.clone(), students[3].name.clone()],
         "Kate and Harry are in category reading"
     );
     assert_eq!(
         match_result.placed.get(&categories[2].name).unwrap(),
-        &vec![students[1].clone()],
+        &vec![students[1].name.clone()],
         "Suze is in category waling"
     );
     assert_eq!(
         match_result.placed.get(&categories[0].name).unwrap(),
-        &vec![students[0].clone()],
+        &vec![students[0].name.clone()],
         "Bert is in category cooking"
     );
     assert_eq!(
         match_result.not_placable,
-        vec![students[4].clone()],
+        vec![students[4].name.clone()],
         "Lisa was not placable"
     );
 }
 
 #[test]
 fn test_match_students_to_multiple_categories() {
-    let (students, mut categories) = get_data(3, 1, 3);
+    let (students, categories) = get_data(3, 1, 3);
     let mut rng = StepRng::new(2, 0);
 
     let match_result =
-        match_students_to_multiple_categories(students.clone(), &mut categories, &mut rng);
+        match_students_to_multiple_categories(students.clone(), &categories, &mut rng).unwrap();
 
     assert_eq!(
         match_result.placed.get(&categories[2].name).unwrap(),
         &vec![
-            students[1].clone(),
-            students[2].clone(),
-            students[3].clone()
+            students[1].name.clone(),
+            students[2].name.clone(),
+            students[3].name.clone()
         ],
         "Suze, Kate and Harry are in category walking"
     );
     assert_eq!(
         match_result.placed.get(&categories[1].name).unwrap(),
-        &vec![students[2].clone()],
+        &vec![students[2].name.clone()],
         "Kate is in category reading"
     );
     assert_eq!(
         match_result.placed.get(&categories[0].name).unwrap(),
         &vec![
-            students[0].clone(),
-            students[4].clone(),
-            students[1].clone()
+            students[0].name.clone(),
+            students[4].name.clone(),
+            students[1].name.clone()
         ],
         "Bert, Lisa and Suze are in category cooking"
     );
@@ -138,73 +113,900 @@ fn test_match_students_to_multiple_categories() {
 
 #[test]
 fn test_match_students_to_multiple_categories_not_enough_places() {
-    let (students, mut categories) = get_data(1, 2, 1);
+    let (students, categories) = get_data(1, 2, 1);
     let mut rng = StepRng::new(2, 0);
 
     let match_result =
-        match_students_to_multiple_categories(students.clone(), &mut categories, &mut rng);
+        match_students_to_multiple_categories(students.clone(), &categories, &mut rng).unwrap();
 
     assert_eq!(
         match_result.placed.get(&categories[1].name).unwrap(),
-        &vec![students[2].clone(), students[3].clone()],
+        &vec![students[2].name.clone(), students[3].name.clone()],
         "Kate and Harry are in category reading"
     );
     assert_eq!(
         match_result.placed.get(&categories[2].name).unwrap(),
-        &vec![students[1].clone()],
+        &vec![students[1].name.clone()],
         "Suze is in category waling"
     );
     assert_eq!(
         match_result.placed.get(&categories[0].name).unwrap(),
-        &vec![students[0].clone()],
+        &vec![students[0].name.clone()],
         "Bert is in category cooking"
     );
     assert_eq!(
         match_result.not_placable,
-        vec![students[4].clone()],
+        vec![students[4].name.clone()],
         "Lisa was not placable"
     );
 }
 
 #[test]
 fn test_match_students_to_multiple_categories_more_than_enough_places() {
-    let (students, mut categories) = get_data(30, 30, 30);
+    let (students, categories) = get_data(30, 30, 30);
     let mut rng = StepRng::new(2, 0);
 
     let match_result =
-        match_students_to_multiple_categories(students.clone(), &mut categories, &mut rng);
+        match_students_to_multiple_categories(students.clone(), &categories, &mut rng).unwrap();
 
     assert_eq!(
         match_result.placed.get(&categories[1].name).unwrap(),
         &vec![
-            students[3].clone(),
-            students[4].clone(),
-            students[0].clone(),
-            students[1].clone(),
-            students[2].clone()
+            students[2].name.clone(),
+            students[0].name.clone(),
+            students[3].name.clone(),
+            students[4].name.clone(),
+            students[1].name.clone()
         ],
-        "Harry, Lisa, Bert, Suze and Kate are in category reading"
+        "Kate, Bert, Harry, Lisa and Suze are in category reading"
     );
     assert_eq!(
         match_result.placed.get(&categories[2].name).unwrap(),
         &vec![
-            students[1].clone(),
-            students[2].clone(),
-            students[3].clone(),
-            students[4].clone(),
-            students[0].clone(),
+            students[1].name.clone(),
+            students[2].name.clone(),
+            students[3].name.clone(),
+            students[0].name.clone(),
+            students[4].name.clone(),
         ],
-        "Suze, Kate, Harry, Lisa and Bert are in category waling"
+        "Suze, Kate, Harry, Bert and Lisa are in category waling"
     );
     assert_eq!(
         match_result.placed.get(&categories[0].name).unwrap(),
         &vec![
-            students[0].clone(),
-            students[4].clone(),
-            students[1].clone(),
-            students[2].clone(),
+            students[0].name.clone(),
+            students[4].name.clone(),
+            students[1].name.clone(),
+            students[2].name.clone(),
         ],
         "Bert, Lisa, Suze and Kate are in category cooking"
     );
-    assert_eq!(match_result.not_placable, vec![], "Everyone is placable");
+    assert!(match_result.not_placable.is_empty(), "Everyone is placable");
+}
+
+#[test]
+fn test_match_students_invalid_input() {
+    let (mut students, categories) = get_data(3, 2, 1);
+    let swimming = Category::new("Swimming", 4);
+    students[4].preferences.push_back(swimming);
+    students.push(students[0].clone());
+
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students, &categories, &mut rng);
+
+    assert_eq!(
+        match_result.unwrap_err(),
+        MatchError::InvalidInput(vec![
+            Violation::UnknownCategory {
+                student: "Lisa".into(),
+                category: "Swimming".into(),
+            },
+            Violation::DuplicateStudent("Bert".into()),
+        ]),
+        "Lisa refers to an unknown category and Bert is listed twice"
+    );
+}
+
+#[test]
+fn test_match_students_keeps_priorities_out_of_students() {
+    let cooking = Category::new("Cooking", 1).with_priority("Suze", 1);
+    let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new());
+    assert!(bert.preferences[0].priorities.is_empty());
+
+    // Students that don't use the constructor, like deserialized ones, lose them in the match.
+    let mut suze = Student::new("Suze", VecDeque::new(), Vec::new());
+    suze.preferences.push_back(cooking.clone());
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(vec![bert, suze], &[cooking], &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placed["Cooking"],
+        vec!["Suze"],
+        "The category keeps its priorities"
+    );
+}
+
+#[test]
+fn test_match_students_lottery_numbers_and_preference_ranks() {
+    let (students, categories) = get_data(3, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.lottery_numbers.len(),
+        students.len(),
+        "Every student has a lottery number"
+    );
+    assert_eq!(
+        match_result.placements["Suze"],
+        vec![Placement {
+            category: categories[2].name.clone(),
+            lottery_number: 1,
+            preference_rank: Some(1),
+            fixed: false,
+        }],
+        "Suze drew the first lot and got her first choice"
+    );
+    assert_eq!(
+        match_result.placements["Kate"],
+        vec![Placement {
+            category: categories[1].name.clone(),
+            lottery_number: 2,
+            preference_rank: Some(2),
+            fixed: false,
+        }],
+        "Kate lost walking to Suze and got her second choice"
+    );
+    assert_eq!(
+        match_result.placements["Harry"][0].preference_rank, None,
+        "Harry was assigned randomly"
+    );
+}
+
+#[test]
+fn test_match_students_waitlists() {
+    let (students, categories) = get_data(3, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.waitlists.get(&categories[2].name).unwrap(),
+        &vec![students[2].name.clone(), students[3].name.clone()],
+        "Kate and Harry are on the waitlist for walking"
+    );
+    assert!(
+        !match_result.waitlists.contains_key(&categories[0].name),
+        "Nobody was rejected by cooking"
+    );
+}
+
+#[test]
+fn test_withdraw_students() {
+    let (students, categories) = get_data(1, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+    let match_result = withdraw_students(
+        students.clone(),
+        &categories,
+        &match_result,
+        &["Suze".to_string()],
+        &mut rng,
+    )
+    .unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![students[2].name.clone()],
+        "Kate moved up to walking"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[1].name).unwrap(),
+        &vec![students[3].name.clone(), students[4].name.clone()],
+        "Harry kept his spot in reading and Lisa got the spot Kate left"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[0].name).unwrap(),
+        &vec![students[0].name.clone()],
+        "Bert is still in category cooking"
+    );
+    assert_eq!(match_result.lottery_numbers["Kate"], 2, "Lots are kept");
+    assert!(match_result.not_placable.is_empty(), "Everyone is placed");
+}
+
+#[test]
+fn test_withdraw_unknown_student() {
+    let (students, categories) = get_data(1, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+    let match_result = withdraw_students(
+        students,
+        &categories,
+        &match_result,
+        &["Ludo".to_string()],
+        &mut rng,
+    );
+
+    assert_eq!(
+        match_result.unwrap_err(),
+        MatchError::InvalidInput(vec![Violation::UnknownStudent("Ludo".into())]),
+        "Ludo was not part of the match"
+    );
+}
+
+#[test]
+fn test_match_students_with_indifference_groups() {
+    let (mut students, categories) = get_data(3, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    // Kate doesn't care whether she is placed in walking or reading
+    students[2] = Student::with_indifference_groups(
+        "Kate",
+        vec![vec![categories[2].clone(), categories[1].clone()]],
+        Vec::new(),
+    );
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placements["Kate"],
+        vec![Placement {
+            category: categories[1].name.clone(),
+            lottery_number: 2,
+            preference_rank: Some(1),
+            fixed: false,
+        }],
+        "Kate got one of her first choices"
+    );
+}
+
+#[test]
+fn test_withdraw_students_with_indifference_groups() {
+    let (mut students, categories) = get_data(1, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    students[2] = Student::with_indifference_groups(
+        "Kate",
+        vec![vec![categories[2].clone(), categories[1].clone()]],
+        Vec::new(),
+    );
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+    let match_result = withdraw_students(
+        students.clone(),
+        &categories,
+        &match_result,
+        &["Suze".to_string()],
+        &mut rng,
+    )
+    .unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![students[3].name.clone()],
+        "Kate is happy in reading, so Harry moved up to walking"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[1].name).unwrap(),
+        &vec![students[2].name.clone(), students[4].name.clone()],
+        "Kate kept her spot in reading and Lisa got the spot Harry left"
+    );
+}
+
+#[test]
+fn test_match_students_min_placements() {
+    let (students, mut categories) = get_data(3, 2, 3);
+    let mut rng = StepRng::new(2, 0);
+
+    // Only Bert and maybe Lisa can end up in cooking
+    categories[0] = categories[0].clone().with_min_placements(3);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(match_result.cancelled, vec![categories[0].name.clone()]);
+    assert!(!match_result.placed.contains_key(&categories[0].name));
+    assert_eq!(
+        match_result.placed.get(&categories[1].name).unwrap(),
+        &vec![students[0].name.clone(), students[4].name.clone()],
+        "Bert and Lisa are in category reading"
+    );
+    assert_eq!(
+        match_result.placements["Bert"][0].preference_rank,
+        Some(2),
+        "Bert got his second choice"
+    );
+
+    let match_result = withdraw_students(
+        students,
+        &categories,
+        &match_result,
+        &["Suze".to_string()],
+        &mut rng,
+    )
+    .unwrap();
+
+    assert_eq!(
+        match_result.cancelled,
+        vec![categories[0].name.clone()],
+        "Cooking stays cancelled"
+    );
+    assert!(!match_result.placed.contains_key(&categories[0].name));
+    assert_eq!(match_result.placements["Bert"][0].preference_rank, Some(2));
+}
+
+/// Make Suze and Kate friends that both prefer walking, then reading
+fn make_friends(students: &mut [Student], categories: &[Category], group: Group) {
+    for student in students[1..3].iter_mut() {
+        student.preferences = VecDeque::from(vec![categories[2].clone(), categories[1].clone()]);
+        student.group = Some(group.clone());
+    }
+}
+
+#[test]
+fn test_match_students_with_hard_group() {
+    let (mut students, categories) = get_data(3, 2, 1);
+    make_friends(&mut students, &categories, Group::hard("Friends"));
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![students[3].name.clone()],
+        "Walking can't hold both friends, so Harry gets it"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[1].name).unwrap(),
+        &vec![students[1].name.clone(), students[2].name.clone()],
+        "Suze and Kate are in category reading together"
+    );
+    assert_eq!(
+        match_result.lottery_numbers["Kate"],
+        match_result.lottery_numbers["Suze"]
+    );
+}
+
+#[test]
+fn test_match_students_with_hard_group_not_enough_places() {
+    let (mut students, categories) = get_data(1, 1, 1);
+    make_friends(&mut students, &categories, Group::hard("Friends"));
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    let mut not_placable = match_result.not_placable.clone();
+    not_placable.sort();
+    assert_eq!(
+        not_placable,
+        vec![students[2].name.clone(), students[1].name.clone()],
+        "No category can hold both friends"
+    );
+}
+
+#[test]
+fn test_match_students_with_soft_group() {
+    let (mut students, categories) = get_data(3, 1, 1);
+    make_friends(&mut students, &categories, Group::soft("Friends"));
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    let reading = match_result.placed.get(&categories[1].name).unwrap();
+    assert_eq!(reading.len(), 1);
+    assert!(
+        reading[0] == "Kate" || reading[0] == "Suze",
+        "One of the friends is in category reading"
+    );
+    assert_ne!(
+        placed_categories(&match_result, "Suze"),
+        placed_categories(&match_result, "Kate"),
+        "The friends are no longer placed as a group"
+    );
+    assert!(match_result.not_placable.is_empty(), "Everyone is placed");
+}
+
+fn placed_categories(match_result: &MatchResult, name: &str) -> Vec<String> {
+    let mut categories: Vec<String> = match_result.placements[name]
+        .iter()
+        .map(|p| p.category.clone())
+        .collect();
+    categories.sort();
+    categories
+}
+
+#[test]
+fn test_match_students_to_multiple_categories_max_categories() {
+    let (mut students, categories) = get_data(30, 30, 30);
+    students[0] = students[0].clone().with_max_categories(2);
+    let options = MultipleCategoryOptions {
+        max_categories: Some(1),
+        ..MultipleCategoryOptions::default()
+    };
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students_to_multiple_categories_with_options(
+        students.clone(),
+        &categories,
+        &options,
+        &mut rng,
+    )
+    .unwrap();
+
+    assert_eq!(
+        placed_categories(&match_result, "Bert"),
+        vec!["Cooking", "Reading"],
+        "Bert can be placed in two categories"
+    );
+    for student in students[1..].iter() {
+        assert_eq!(match_result.placements[&student.name].len(), 1);
+    }
+    assert_eq!(placed_categories(&match_result, "Suze"), vec!["Walking"]);
+}
+
+#[test]
+fn test_match_students_to_multiple_categories_preferences_only() {
+    let (students, categories) = get_data(30, 30, 30);
+    let options = MultipleCategoryOptions {
+        preferences_only: true,
+        ..MultipleCategoryOptions::default()
+    };
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students_to_multiple_categories_with_options(
+        students.clone(),
+        &categories,
+        &options,
+        &mut rng,
+    )
+    .unwrap();
+
+    assert_eq!(
+        placed_categories(&match_result, "Bert"),
+        vec!["Cooking", "Reading", "Walking"]
+    );
+    assert_eq!(
+        placed_categories(&match_result, "Suze"),
+        vec!["Cooking", "Walking"]
+    );
+    assert_eq!(
+        placed_categories(&match_result, "Kate"),
+        vec!["Reading", "Walking"]
+    );
+    assert_eq!(placed_categories(&match_result, "Harry"), vec!["Walking"]);
+    assert_eq!(
+        match_result.placements["Lisa"].len(),
+        1,
+        "Lisa has no preferences, so only the first round places Lisa"
+    );
+}
+
+#[test]
+fn test_match_students_to_multiple_categories_without_fallback() {
+    let cooking = Category::new("Cooking", 1);
+    let reading = Category::new("Reading", 1);
+    let bert = Student::new(
+        "Bert",
+        VecDeque::from(vec![cooking.clone(), reading.clone()]),
+        Vec::new(),
+    );
+    let options = MultipleCategoryOptions {
+        fallback: Fallback::None,
+        ..MultipleCategoryOptions::default()
+    };
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students_to_multiple_categories_with_options(
+        vec![bert],
+        &[cooking, reading],
+        &options,
+        &mut rng,
+    )
+    .unwrap();
+
+    let ranks: Vec<(String, Option<usize>)> = match_result.placements["Bert"]
+        .iter()
+        .map(|p| (p.category.clone(), p.preference_rank))
+        .collect();
+    assert_eq!(
+        ranks,
+        vec![
+            ("Cooking".to_string(), Some(1)),
+            ("Reading".to_string(), Some(2))
+        ],
+        "Bert gets both preferences in order"
+    );
+}
+
+#[test]
+fn test_match_students_to_multiple_categories_time_slots() {
+    let (students, mut categories) = get_data(30, 30, 30);
+    categories[0] = categories[0].clone().with_time_slot(840, 930);
+    categories[1] = categories[1].clone().with_time_slot(900, 960);
+    categories[2] = categories[2].clone().with_time_slot(960, 1020);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result =
+        match_students_to_multiple_categories(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        placed_categories(&match_result, "Bert"),
+        vec!["Cooking", "Walking"],
+        "Reading overlaps with cooking"
+    );
+    for student in students.iter() {
+        let placed = placed_categories(&match_result, &student.name);
+        assert!(
+            !(placed.contains(&categories[0].name) && placed.contains(&categories[1].name)),
+            "{} is not in cooking and reading",
+            student.name
+        );
+    }
+}
+
+#[test]
+fn test_match_students_to_multiple_categories_time_slots_without_fallback() {
+    let cooking = Category::new("Cooking", 1).with_time_slot(840, 930);
+    let reading = Category::new("Reading", 1).with_time_slot(900, 960);
+    let walking = Category::new("Walking", 1).with_time_slot(960, 1020);
+    let bert = Student::new(
+        "Bert",
+        VecDeque::from(vec![cooking.clone(), reading.clone(), walking.clone()]),
+        Vec::new(),
+    );
+    let options = MultipleCategoryOptions {
+        fallback: Fallback::None,
+        ..MultipleCategoryOptions::default()
+    };
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students_to_multiple_categories_with_options(
+        vec![bert],
+        &[cooking, reading, walking],
+        &options,
+        &mut rng,
+    )
+    .unwrap();
+
+    let ranks: Vec<(String, Option<usize>)> = match_result.placements["Bert"]
+        .iter()
+        .map(|p| (p.category.clone(), p.preference_rank))
+        .collect();
+    assert_eq!(
+        ranks,
+        vec![
+            ("Cooking".to_string(), Some(1)),
+            ("Walking".to_string(), Some(3))
+        ],
+        "Reading overlaps with cooking, walking doesn't"
+    );
+}
+
+#[test]
+fn test_match_students_with_reserve() {
+    let (mut students, mut categories) = get_data(3, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    // Suze gets walking without a reserve, see test_match_students
+    students[3] = students[3].clone().with_attribute("low-income");
+    categories[2] = categories[2].clone().with_reserve("low-income", 1);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![students[3].name.clone()],
+        "Harry is in category walking"
+    );
+}
+
+#[test]
+fn test_match_students_to_multiple_categories_with_reserve() {
+    let cooking = Category::new("Cooking", 3)
+        .with_reserve("low-income", 1)
+        .with_priority("Kate", 1);
+    let reading = Category::new("Reading", 1);
+    let walking = Category::new("Walking", 1);
+
+    let bert = Student::new("Bert", VecDeque::from(vec![cooking.clone()]), Vec::new());
+    let suze = Student::new("Suze", VecDeque::from(vec![cooking.clone()]), Vec::new())
+        .with_attribute("low-income");
+    let kate = Student::new(
+        "Kate",
+        VecDeque::from(vec![reading.clone(), cooking.clone()]),
+        Vec::new(),
+    );
+    let harry = Student::new(
+        "Harry",
+        VecDeque::from(vec![walking.clone(), cooking.clone()]),
+        Vec::new(),
+    )
+    .with_attribute("low-income");
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students_to_multiple_categories(
+        vec![bert, suze, kate, harry],
+        &[cooking, reading, walking],
+        &mut rng,
+    )
+    .unwrap();
+
+    // Suze takes the reserved seat in the first round, so Kate gets the last seat by priority.
+    let mut cooking: Vec<&str> = match_result.placed["Cooking"]
+        .iter()
+        .map(|s| s.as_str())
+        .collect();
+    cooking.sort_unstable();
+    assert_eq!(cooking, vec!["Bert", "Kate", "Suze"]);
+}
+
+#[test]
+fn test_match_students_with_fixed_placements() {
+    let (students, mut categories) = get_data(3, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    // Kate returns to walking, Harry is placed in reading by the school board
+    categories[2] = categories[2].clone().with_fixed_placement("Kate");
+    categories[1] = categories[1].clone().with_fixed_placement("Harry");
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![students[2].name.clone()],
+        "Kate is in category walking"
+    );
+    assert_eq!(
+        match_result.placed[&categories[1].name][0],
+        students[3].name
+    );
+    assert_eq!(match_result.placed[&categories[0].name].len(), 3);
+    assert!(match_result.placements["Kate"][0].fixed);
+    assert_eq!(match_result.placements["Kate"][0].preference_rank, Some(1));
+    assert_eq!(match_result.placements["Harry"][0].preference_rank, None);
+    assert!(!match_result.placements["Suze"][0].fixed);
+
+    let match_result = withdraw_students(
+        students.clone(),
+        &categories,
+        &match_result,
+        &["Bert".to_string()],
+        &mut rng,
+    )
+    .unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![students[2].name.clone()],
+        "Kate stays in category walking"
+    );
+    assert!(match_result.placements["Harry"][0].fixed);
+}
+
+#[test]
+fn test_match_students_to_multiple_categories_with_fixed_placements() {
+    let (students, mut categories) = get_data(30, 30, 30);
+    categories[1] = categories[1].clone().with_fixed_placement("Harry");
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result =
+        match_students_to_multiple_categories(students.clone(), &categories, &mut rng).unwrap();
+
+    let reading = &match_result.placed[&categories[1].name];
+    assert_eq!(reading[0], students[3].name);
+    assert_eq!(
+        reading.iter().filter(|&s| s == "Harry").count(),
+        1,
+        "Harry is placed in reading once"
+    );
+}
+
+#[test]
+fn test_match_students_without_fallback() {
+    let (students, categories) = get_data(2, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result =
+        match_students_with_fallback(students.clone(), &categories, &Fallback::None, &mut rng)
+            .unwrap();
+
+    assert_eq!(
+        match_result.not_placable,
+        vec![students[4].name.clone(), students[3].name.clone()],
+        "Harry lost walking to Suze and Lisa did not list any category"
+    );
+    assert_eq!(match_result.placed.values().flatten().count(), 3);
+    assert_eq!(match_result.fallback, Fallback::None);
+
+    // Withdrawing keeps the policy, so nobody gets the spot Bert left.
+    let match_result = withdraw_students(
+        students.clone(),
+        &categories,
+        &match_result,
+        &["Bert".to_string()],
+        &mut rng,
+    )
+    .unwrap();
+
+    assert_eq!(match_result.not_placable.len(), 2);
+    assert!(!match_result.placed.contains_key(&categories[0].name));
+    assert_eq!(match_result.fallback, Fallback::None);
+}
+
+#[test]
+fn test_match_students_with_second_round() {
+    let (students, categories) = get_data(2, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let mut preferences = BTreeMap::new();
+    preferences.insert(
+        "Lisa".to_string(),
+        vec!["Walking".to_string(), "Cooking".to_string()],
+    );
+    let fallback = Fallback::SecondRound(preferences);
+
+    let match_result =
+        match_students_with_fallback(students.clone(), &categories, &fallback, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placements["Lisa"],
+        vec![Placement {
+            category: "Cooking".into(),
+            lottery_number: match_result.lottery_numbers["Lisa"],
+            preference_rank: None,
+            fixed: false,
+        }],
+        "Walking is full, so Lisa gets cooking"
+    );
+    assert_eq!(match_result.fallback, fallback);
+
+    let mut preferences = BTreeMap::new();
+    preferences.insert("Lisa".to_string(), vec!["Swimming".to_string()]);
+    assert_eq!(
+        match_students_with_fallback(
+            students,
+            &categories,
+            &Fallback::SecondRound(preferences),
+            &mut rng
+        )
+        .unwrap_err(),
+        MatchError::InvalidInput(vec![Violation::UnknownCategory {
+            student: "Lisa".into(),
+            category: "Swimming".into(),
+        }])
+    );
+}
+
+/// Bert and Suze each have priority in the category the other prefers, and Kate ties with
+/// Suze in reading
+fn get_tied_data() -> (Vec<Student>, Vec<Category>) {
+    let cooking = Category::new("Cooking", 1).with_priority("Suze", 1);
+    let reading = Category::new("Reading", 1).with_priority("Bert", 1);
+    let walking = Category::new("Walking", 1);
+
+    let bert = Student::new(
+        "Bert",
+        VecDeque::from(vec![cooking.clone(), reading.clone()]),
+        Vec::new(),
+    );
+    let kate = Student::new(
+        "Kate",
+        VecDeque::from(vec![reading.clone(), walking.clone()]),
+        Vec::new(),
+    );
+    let suze = Student::new(
+        "Suze",
+        VecDeque::from(vec![reading.clone(), cooking.clone()]),
+        Vec::new(),
+    );
+
+    (vec![bert, kate, suze], vec![cooking, reading, walking])
+}
+
+#[test]
+fn test_improve_placements() {
+    let (students, categories) = get_tied_data();
+    let mut rng = StepRng::new(2, 0);
+
+    // Kate draws a better lot than Suze and pushes her out of reading.
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+    assert_eq!(match_result.placed["Cooking"][0], "Suze");
+    assert_eq!(match_result.placed["Reading"][0], "Bert");
+
+    let improved = improve_placements(students, &categories, &match_result).unwrap();
+
+    assert_eq!(improved.placed["Cooking"][0], "Bert");
+    assert_eq!(improved.placed["Reading"][0], "Suze");
+    assert_eq!(improved.placed["Walking"][0], "Kate");
+    assert_eq!(improved.placements["Bert"][0].category, "Cooking");
+    assert_eq!(improved.placements["Bert"][0].preference_rank, Some(1));
+    assert_eq!(improved.placements["Suze"][0].preference_rank, Some(1));
+    assert_eq!(improved.lottery_numbers, match_result.lottery_numbers);
+    assert_eq!(
+        improved
+            .waitlists
+            .iter()
+            .map(|(category, waitlist)| (category.as_str(), waitlist[0].as_str()))
+            .collect::<Vec<_>>(),
+        vec![("Reading", "Kate")],
+        "Only Kate still waits for a spot she prefers"
+    );
+}
+
+#[test]
+fn test_improve_placements_keeps_stability() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut improved_students = 0;
+
+    for _ in 0..20 {
+        let categories: Vec<Category> = (0..5)
+            .map(|i| {
+                (0..20).fold(Category::new(&format!("Category {}", i), 3), |c, s| {
+                    c.with_priority(&format!("Student {}", s), rng.gen_range(0, 2))
+                })
+            })
+            .collect();
+        let students: Vec<Student> = (0..20)
+            .map(|s| {
+                let mut preferences = categories.clone();
+                preferences.shuffle(&mut rng);
+                preferences.truncate(rng.gen_range(1, 6));
+                Student::new(
+                    &format!("Student {}", s),
+                    VecDeque::from(preferences),
+                    Vec::new(),
+                )
+            })
+            .collect();
+
+        let match_result =
+            match_students_with_fallback(students.clone(), &categories, &Fallback::None, &mut rng)
+                .unwrap();
+        let improved = improve_placements(students.clone(), &categories, &match_result).unwrap();
+
+        for student in students.iter() {
+            let rank = |result: &MatchResult| {
+                result
+                    .placements
+                    .get(&student.name)
+                    .and_then(|p| p[0].preference_rank)
+                    .unwrap_or(usize::MAX)
+            };
+            assert!(rank(&improved) <= rank(&match_result));
+            if rank(&improved) < rank(&match_result) {
+                improved_students += 1;
+            }
+        }
+
+        // Only lots can block, as a student with a higher priority would
+        let blocking_pairs = find_blocking_pairs(&students, &categories, &improved).unwrap();
+        for pair in blocking_pairs {
+            let category = categories.iter().find(|c| c.name == pair.category).unwrap();
+            let displaced = pair.displaced.expect("Categories are full");
+            assert_eq!(
+                category.priority(&pair.student),
+                category.priority(&displaced)
+            );
+        }
+    }
+
+    assert!(improved_students > 0, "Some students trade spots");
+}
+
+#[test]
+fn test_improve_placements_rejects_groups() {
+    let (mut students, categories) = get_data(3, 2, 1);
+    make_friends(&mut students, &categories, Group::hard("Friends"));
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        improve_placements(students, &categories, &match_result).unwrap_err(),
+        MatchError::InvalidInput(vec![Violation::UnsupportedGroup("Friends".into())])
+    );
 }
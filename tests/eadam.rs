@@ -0,0 +1,120 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::get_data;
+use matchmaker::da_stb::DaStb;
+use matchmaker::eadam::{match_students, Eadam};
+use matchmaker::{Category, Fallback, Group, MatchError, MatchResult, Matcher, Student, Violation};
+use rand::rngs::mock::StepRng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+
+/// Students with random preferences and categories with random priorities, where every
+/// student consents
+fn get_random_data(rng: &mut impl Rng) -> (Vec<Student>, Vec<Category>) {
+    let categories: Vec<Category> = (0..6)
+        .map(|i| {
+            (0..30).fold(Category::new(&format!("Category {}", i), 4), |c, s| {
+                c.with_priority(&format!("Student {}", s), rng.gen_range(0, 3))
+            })
+        })
+        .collect();
+    let students = (0..30)
+        .map(|s| {
+            let mut preferences: Vec<Category> = categories.clone();
+            preferences.retain(|_| rng.gen_bool(0.6));
+            for i in (1..preferences.len()).rev() {
+                preferences.swap(i, rng.gen_range(0, i + 1));
+            }
+            Student::new(
+                &format!("Student {}", s),
+                VecDeque::from(preferences),
+                Vec::new(),
+            )
+            .with_consent()
+        })
+        .collect();
+    (students, categories)
+}
+
+#[test]
+fn test_match_students_without_consent() {
+    let (students, categories) = get_data(1, 1, 1);
+
+    let match_result =
+        match_students(students.clone(), &categories, &mut StepRng::new(2, 0)).unwrap();
+    let da_result = DaStb
+        .match_students(students, &categories, &mut StepRng::new(2, 0))
+        .unwrap();
+
+    assert_eq!(match_result.placed, da_result.placed);
+    assert_eq!(match_result.not_placable, da_result.not_placable);
+    assert!(match_result.waivers.is_empty(), "Nobody waived priority");
+}
+
+#[test]
+fn test_match_students_dominates_da_stb() {
+    let mut improved = false;
+
+    for seed in 0..20 {
+        let (students, categories) = get_random_data(&mut StdRng::seed_from_u64(seed));
+
+        let match_result = Eadam
+            .match_students_with_fallback(
+                students.clone(),
+                &categories,
+                &Fallback::None,
+                &mut StdRng::seed_from_u64(seed),
+            )
+            .unwrap();
+        let da_result = DaStb
+            .match_students_with_fallback(
+                students.clone(),
+                &categories,
+                &Fallback::None,
+                &mut StdRng::seed_from_u64(seed),
+            )
+            .unwrap();
+
+        let rank = |result: &MatchResult, name: &str| {
+            result
+                .placements
+                .get(name)
+                .and_then(|p| p[0].preference_rank)
+                .unwrap_or(usize::MAX)
+        };
+        for student in students.iter() {
+            let eadam_rank = rank(&match_result, &student.name);
+            let da_rank = rank(&da_result, &student.name);
+            assert!(
+                eadam_rank <= da_rank,
+                "{} is placed worse with seed {}",
+                student.name,
+                seed
+            );
+            improved |= eadam_rank < da_rank;
+        }
+    }
+
+    assert!(improved, "Waivers placed some student better");
+}
+
+#[test]
+fn test_match_students_rejects_groups() {
+    let (mut students, categories) = get_data(3, 2, 1);
+    students[0].group = Some(Group::hard("Friends"));
+    students[4].group = Some(Group::hard("Friends"));
+    students[4].preferences = students[0].preferences.clone();
+    let mut rng = StepRng::new(2, 0);
+
+    assert_eq!(
+        match_students(students, &categories, &mut rng).unwrap_err(),
+        MatchError::InvalidInput(vec![Violation::UnsupportedGroup("Friends".into())])
+    );
+}
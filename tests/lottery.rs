@@ -0,0 +1,70 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::get_data;
+use matchmaker::{da_mtb, da_stb, immediate_acceptance, ttc, Fallback, Lottery};
+
+#[test]
+fn test_draw_is_reproducible() {
+    let (students, categories) = get_data(1, 1, 1);
+
+    let lottery = Lottery::new("42", &students, &categories);
+    let seed = lottery.seed();
+    let match_result = lottery
+        .draw(students.clone(), &categories, da_stb::match_students)
+        .unwrap();
+
+    assert_eq!(match_result.seed, Some(seed.clone()));
+
+    for _ in 0..10 {
+        let reproduced = Lottery::replay(
+            &seed,
+            match_result.input_hash.as_ref().unwrap(),
+            &students,
+            &categories,
+            &Fallback::Random,
+        )
+        .unwrap()
+        .draw(students.clone(), &categories, da_stb::match_students)
+        .unwrap();
+
+        assert_eq!(format!("{:?}", reproduced), format!("{:?}", match_result));
+    }
+}
+
+#[test]
+fn test_draw_with_every_mechanism() {
+    let (students, categories) = get_data(1, 1, 1);
+    let lottery = Lottery::new("42", &students, &categories);
+
+    let match_results = vec![
+        lottery
+            .clone()
+            .draw(students.clone(), &categories, da_stb::match_students),
+        lottery.clone().draw(
+            students.clone(),
+            &categories,
+            da_stb::match_students_to_multiple_categories,
+        ),
+        lottery
+            .clone()
+            .draw(students.clone(), &categories, da_mtb::match_students),
+        lottery
+            .clone()
+            .draw(students.clone(), &categories, ttc::match_students),
+        lottery.clone().draw(
+            students.clone(),
+            &categories,
+            immediate_acceptance::match_students,
+        ),
+    ];
+
+    for match_result in match_results {
+        assert_eq!(match_result.unwrap().seed, Some(lottery.seed()));
+    }
+}
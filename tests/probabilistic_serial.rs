@@ -0,0 +1,124 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::get_data;
+use matchmaker::probabilistic_serial::{match_students, random_assignment, sample};
+use matchmaker::{Group, MatchError, Violation};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::{BTreeMap, VecDeque};
+
+#[test]
+fn test_random_assignment() {
+    let (students, categories) = get_data(1, 1, 1);
+
+    let assignment = random_assignment(&students, &categories).unwrap();
+
+    // Walking runs out at a third, cooking at two thirds and reading when time is up.
+    let probabilities = &assignment.placement_probabilities;
+    let not_placable = &assignment.not_placable_probabilities;
+    assert_close(probabilities["Bert"]["Cooking"], 2.0 / 3.0);
+    assert_close(probabilities["Bert"]["Reading"], 1.0 / 3.0);
+    assert_close(probabilities["Suze"]["Walking"], 1.0 / 3.0);
+    assert_close(probabilities["Suze"]["Cooking"], 1.0 / 3.0);
+    assert_close(probabilities["Kate"]["Walking"], 1.0 / 3.0);
+    assert_close(probabilities["Kate"]["Reading"], 2.0 / 3.0);
+    assert_close(probabilities["Harry"]["Walking"], 1.0 / 3.0);
+    assert_close(not_placable["Bert"], 0.0);
+    assert_close(not_placable["Suze"], 1.0 / 3.0);
+    assert_close(not_placable["Kate"], 0.0);
+    assert_close(not_placable["Harry"], 2.0 / 3.0);
+    assert_close(not_placable["Lisa"], 1.0);
+}
+
+fn assert_close(probability: f64, expected: f64) {
+    assert!(
+        (probability - expected).abs() < 1e-9,
+        "{} is not {}",
+        probability,
+        expected
+    );
+}
+
+#[test]
+fn test_sample_follows_probabilities() {
+    let (students, categories) = get_data(1, 1, 1);
+    let assignment = random_assignment(&students, &categories).unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+
+    let runs = 2000;
+    let mut counts: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for _ in 0..runs {
+        let match_result = sample(students.clone(), &categories, &assignment, &mut rng).unwrap();
+        for (category, placed) in match_result.placed.iter() {
+            assert!(placed.len() <= 1, "No category holds more than one student");
+            for student in placed {
+                *counts
+                    .entry((student.clone(), category.clone()))
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    for (student, probabilities) in assignment.placement_probabilities.iter() {
+        for (category, probability) in probabilities.iter() {
+            let count = counts
+                .get(&(student.clone(), category.clone()))
+                .copied()
+                .unwrap_or(0);
+            let share = count as f64 / runs as f64;
+            assert!(
+                (share - probability).abs() < 0.05,
+                "{} is placed in {} {} of the time instead of {}",
+                student,
+                category,
+                share,
+                probability
+            );
+        }
+    }
+}
+
+#[test]
+fn test_sample_rejects_invalid_probabilities() {
+    let (students, categories) = get_data(1, 1, 1);
+    let mut assignment = random_assignment(&students, &categories).unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+
+    // Bert can't be placed in cooking and reading both for sure, nor can cooking hold Bert
+    // and Suze
+    *assignment
+        .placement_probabilities
+        .get_mut("Bert")
+        .unwrap()
+        .get_mut("Cooking")
+        .unwrap() = 1.0;
+
+    assert_eq!(
+        sample(students, &categories, &assignment, &mut rng).unwrap_err(),
+        MatchError::InvalidInput(vec![
+            Violation::InvalidProbabilities("Bert".into()),
+            Violation::InvalidProbabilities("Cooking".into()),
+        ])
+    );
+}
+
+#[test]
+fn test_match_students_rejects_groups() {
+    let (mut students, categories) = get_data(1, 1, 1);
+    for student in students[..2].iter_mut() {
+        student.preferences = VecDeque::from(vec![categories[0].clone()]);
+        student.group = Some(Group::hard("Friends"));
+    }
+    let mut rng = StdRng::seed_from_u64(42);
+
+    assert_eq!(
+        match_students(students, &categories, &mut rng).unwrap_err(),
+        MatchError::InvalidInput(vec![Violation::UnsupportedGroup("Friends".into())])
+    );
+}
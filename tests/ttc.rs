@@ -0,0 +1,181 @@
+// Copyright (c) 2020 Delirious Penguin
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::get_data;
+use matchmaker::{
+    da_stb,
+    ttc::{match_students, Ttc},
+    Category, Matcher, Mechanism, MultipleCategoryOptions, Student,
+};
+use rand::rngs::mock::StepRng;
+use std::collections::VecDeque;
+
+#[test]
+fn test_match_students() {
+    let (students, categories) = get_data(3, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![students[1].name.clone()],
+        "Suze is in category walking"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[1].name).unwrap(),
+        &vec![students[2].name.clone(), students[3].name.clone()],
+        "Kate and Harry are in category reading"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[0].name).unwrap(),
+        &vec![students[0].name.clone(), students[4].name.clone()],
+        "Bert and Lisa are in category cooking"
+    );
+    assert!(match_result.not_placable.is_empty(), "Everyone is placed");
+}
+
+#[test]
+fn test_match_students_not_enough_places() {
+    let (students, categories) = get_data(1, 1, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    assert_eq!(
+        match_result.placed.get(&categories[0].name).unwrap(),
+        &vec![students[0].name.clone()],
+        "Bert is in category cooking"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[1].name).unwrap(),
+        &vec![students[2].name.clone()],
+        "Kate is in category reading"
+    );
+    assert_eq!(
+        match_result.placed.get(&categories[2].name).unwrap(),
+        &vec![students[1].name.clone()],
+        "Suze is in category walking"
+    );
+    assert_eq!(
+        match_result.not_placable,
+        vec![students[3].name.clone(), students[4].name.clone()],
+        "Harry and Lisa were not placable"
+    );
+}
+
+#[test]
+fn test_match_students_pareto_improves_deferred_acceptance() {
+    let cooking = Category::new("Cooking", 1)
+        .with_priority("Bert", 2)
+        .with_priority("Kate", 1);
+    let reading = Category::new("Reading", 1)
+        .with_priority("Suze", 2)
+        .with_priority("Bert", 1);
+    let walking = Category::new("Walking", 1)
+        .with_priority("Kate", 2)
+        .with_priority("Bert", 1);
+
+    let bert = Student::new(
+        "Bert",
+        VecDeque::from(vec![reading.clone(), cooking.clone(), walking.clone()]),
+        Vec::new(),
+    );
+    let suze = Student::new(
+        "Suze",
+        VecDeque::from(vec![cooking.clone(), reading.clone(), walking.clone()]),
+        Vec::new(),
+    );
+    let kate = Student::new(
+        "Kate",
+        VecDeque::from(vec![cooking.clone(), reading.clone(), walking.clone()]),
+        Vec::new(),
+    );
+
+    let categories = vec![cooking, reading, walking];
+    let students = vec![bert, suze, kate];
+
+    let mut rng = StepRng::new(2, 0);
+    let da_result = da_stb::match_students(students.clone(), &categories, &mut rng).unwrap();
+    let mut rng = StepRng::new(2, 0);
+    let ttc_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    // Bert and Suze both get their second choice with deferred acceptance...
+    assert_eq!(
+        da_result.placed[&categories[0].name],
+        vec![students[0].name.clone()]
+    );
+    assert_eq!(
+        da_result.placed[&categories[1].name],
+        vec![students[1].name.clone()]
+    );
+    assert_eq!(
+        da_result.placed[&categories[2].name],
+        vec![students[2].name.clone()]
+    );
+
+    // ...but trade their priorities to both get their first choice with top trading cycles.
+    assert_eq!(
+        ttc_result.placed[&categories[0].name],
+        vec![students[1].name.clone()]
+    );
+    assert_eq!(
+        ttc_result.placed[&categories[1].name],
+        vec![students[0].name.clone()]
+    );
+    assert_eq!(
+        ttc_result.placed[&categories[2].name],
+        vec![students[2].name.clone()]
+    );
+}
+
+#[test]
+fn test_match_students_to_multiple_categories() {
+    let (students, categories) = get_data(3, 2, 1);
+    let mut rng = StepRng::new(2, 0);
+
+    let match_result = Ttc
+        .match_students_to_multiple_categories(
+            students.clone(),
+            &categories,
+            &MultipleCategoryOptions::default(),
+            &mut rng,
+        )
+        .unwrap();
+
+    let placed: usize = match_result.placed.values().map(|p| p.len()).sum();
+    assert_eq!(placed, 6, "Every category is full");
+    assert!(
+        match_result.placements.values().any(|p| p.len() == 2),
+        "The last spot goes to a student that is already placed"
+    );
+    assert!(
+        match_result.placements["Harry"]
+            .iter()
+            .all(|p| p.category != categories[0].name),
+        "Harry excludes cooking"
+    );
+}
+
+#[test]
+fn test_mechanism_matches_like_module() {
+    let (students, categories) = get_data(1, 1, 1);
+
+    let mut rng = StepRng::new(2, 0);
+    let match_result = match_students(students.clone(), &categories, &mut rng).unwrap();
+
+    let matcher: &dyn Matcher = &Mechanism::Ttc;
+    let mut rng = StepRng::new(2, 0);
+    assert_eq!(
+        matcher
+            .match_students(students, &categories, &mut rng)
+            .unwrap()
+            .placed,
+        match_result.placed
+    );
+}